@@ -1,12 +1,19 @@
 use std::hint::black_box;
 use std::time::Duration;
 use criterion::{criterion_group, criterion_main, Criterion, BenchmarkId, Throughput, BatchSize};
-use learning_lsm_write_path::WritePath;
+use learning_lsm_write_path::{Options, WritePath};
 
 const KEY_SIZE: usize = 16;
 const VALUE_SIZE: usize = 100;
 const MEMTABLE_SIZE_THRESHOLD: usize = 64 * 1024 * 1024; // 64 MB
 
+fn bench_options() -> Options {
+    Options::builder()
+        .write_buffer_size(MEMTABLE_SIZE_THRESHOLD)
+        .build()
+        .unwrap()
+}
+
 fn generate_key(i: u64) -> Vec<u8> {
     format!("{:016}", i).into_bytes()
 }
@@ -52,7 +59,7 @@ fn benchmark_bulkload_random(c: &mut Criterion) {
                     },
                     |(keys, value)| {
                         let temp_dir = tempfile::tempdir().unwrap();
-                        let write_path = WritePath::new(temp_dir.path(), MEMTABLE_SIZE_THRESHOLD).unwrap();
+                        let write_path = WritePath::open(temp_dir.path(), bench_options()).unwrap();
 
                         for key_num in keys {
                             let key = generate_key(key_num);
@@ -93,7 +100,7 @@ fn benchmark_bulkload_sequential(c: &mut Criterion) {
                     || generate_value(VALUE_SIZE),
                     |value| {
                         let temp_dir = tempfile::tempdir().unwrap();
-                        let write_path = WritePath::new(temp_dir.path(), MEMTABLE_SIZE_THRESHOLD).unwrap();
+                        let write_path = WritePath::open(temp_dir.path(), bench_options()).unwrap();
 
                         for i in 0..num_keys {
                             let key = generate_key(i);
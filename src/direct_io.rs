@@ -0,0 +1,244 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use crate::file_system::WritableFile;
+
+/// Direct I/Oのバッファ、offset、長さのアライメント
+///
+/// 多くのデバイスの論理ブロックサイズ（512Bか4KB）の倍数
+pub(crate) const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// DirectFileWriterのバッファサイズ（アライメントの倍数）
+const DIRECT_WRITE_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// 書き込み用にページキャッシュを通さずにファイルを作成する
+///
+/// ファイルシステムがDirect I/Oに対応していなければ（tmpfsなど）Noneを返すので、
+/// 呼び出し側は通常のI/Oにフォールバックする
+pub(crate) fn create_direct(path: &Path) -> io::Result<Option<File>> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    open_direct_with(options, path, Probe::Write)
+}
+
+/// 読み込み用にページキャッシュを通さずにファイルを開く（非対応ならNone）
+pub(crate) fn open_direct(path: &Path) -> io::Result<Option<File>> {
+    let mut options = OpenOptions::new();
+    options.read(true);
+    open_direct_with(options, path, Probe::Read)
+}
+
+/// O_DIRECTで開けても、アライメントが足りないなどで最初のI/OがEINVALになるファイルシステムがある。
+/// 開いた直後に1ブロック分のI/Oを試し、失敗したら通常のI/Oにフォールバックする
+#[derive(Clone, Copy)]
+enum Probe {
+    Read,
+    /// 作成したばかりの空のファイルに書いて、切り詰め直す
+    Write,
+}
+
+#[cfg(target_os = "linux")]
+fn open_direct_with(mut options: OpenOptions, path: &Path, probe: Probe) -> io::Result<Option<File>> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    options.custom_flags(libc::O_DIRECT);
+    let file = match options.open(path) {
+        Ok(file) => file,
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut buf = AlignedBuf::new(DIRECT_IO_ALIGNMENT);
+    let result = match probe {
+        Probe::Read => file.read_at(buf.as_mut_slice(), 0).map(drop),
+        Probe::Write => file.write_all_at(buf.as_slice(), 0).and_then(|()| file.set_len(0)),
+    };
+    match result {
+        Ok(()) => Ok(Some(file)),
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// macOSにはO_DIRECTがないので、開いた後にF_NOCACHEでページキャッシュを無効にする
+#[cfg(target_os = "macos")]
+fn open_direct_with(options: OpenOptions, path: &Path, _probe: Probe) -> io::Result<Option<File>> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = options.open(path)?;
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_NOCACHE, 1) } == -1 {
+        return Ok(None);
+    }
+    Ok(Some(file))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn open_direct_with(_options: OpenOptions, _path: &Path, _probe: Probe) -> io::Result<Option<File>> {
+    Ok(None)
+}
+
+fn align_up(n: usize) -> usize {
+    n.div_ceil(DIRECT_IO_ALIGNMENT) * DIRECT_IO_ALIGNMENT
+}
+
+/// 先頭がアライメントされたバッファ
+struct AlignedBuf {
+    raw: Vec<u8>,
+    start: usize,
+    capacity: usize,
+}
+
+impl AlignedBuf {
+    fn new(capacity: usize) -> Self {
+        let raw = vec![0u8; capacity + DIRECT_IO_ALIGNMENT];
+        let start = raw.as_ptr().align_offset(DIRECT_IO_ALIGNMENT);
+        Self { raw, start, capacity }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.raw[self.start..self.start + self.capacity]
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.raw[self.start..self.start + self.capacity]
+    }
+}
+
+/// Direct I/Oで開いたファイルへの書き込み
+///
+/// アライメントされたバッファに溜めて、満杯になるたびにバッファ単位で書き出す。
+/// 最後の端数は`finish()`でアライメントまでゼロ埋めして書き、ファイルを本来の長さに切り詰める
+pub(crate) struct DirectFileWriter {
+    file: File,
+    buf: AlignedBuf,
+    /// バッファに溜まっているバイト数
+    filled: usize,
+    /// 書き出し済みのバイト数（常にアライメントの倍数）
+    offset: u64,
+}
+
+impl DirectFileWriter {
+    pub(crate) fn new(file: File) -> Self {
+        Self {
+            file,
+            buf: AlignedBuf::new(DIRECT_WRITE_BUFFER_SIZE),
+            filled: 0,
+            offset: 0,
+        }
+    }
+}
+
+impl WritableFile for DirectFileWriter {
+    /// 端数をアライメントまでゼロ埋めして書き、本来の長さに切り詰める
+    fn finish(&mut self) -> io::Result<()> {
+        if self.filled > 0 {
+            let len = self.offset + self.filled as u64;
+            let padded = align_up(self.filled);
+            self.buf.as_mut_slice()[self.filled..padded].fill(0);
+            self.file.write_all_at(&self.buf.as_slice()[..padded], self.offset)?;
+            self.file.set_len(len)?;
+            // 端数はバッファに残し、続けて書かれたら同じoffsetから書き直す
+            if padded == self.buf.capacity {
+                self.offset += padded as u64;
+                self.filled = 0;
+            }
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+}
+
+impl Write for DirectFileWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = data.len().min(self.buf.capacity - self.filled);
+        self.buf.as_mut_slice()[self.filled..self.filled + n].copy_from_slice(&data[..n]);
+        self.filled += n;
+
+        if self.filled == self.buf.capacity {
+            self.file.write_all_at(self.buf.as_slice(), self.offset)?;
+            self.offset += self.filled as u64;
+            self.filled = 0;
+        }
+        Ok(n)
+    }
+
+    /// 端数はアライメントできないので、finish()まで書き出さない
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Direct I/Oで開いたファイルから読む
+///
+/// アライメントした範囲をまとめて読み、必要な部分だけをコピーする
+pub(crate) fn read_exact_at_aligned(file: &File, dst: &mut [u8], offset: u64) -> io::Result<()> {
+    let start = offset - offset % DIRECT_IO_ALIGNMENT as u64;
+    let head = (offset - start) as usize;
+    let mut buf = AlignedBuf::new(align_up(head + dst.len()));
+
+    let want = head + dst.len();
+    let mut read = 0;
+    while read < want {
+        let n = match file.read_at(&mut buf.as_mut_slice()[read..], start + read as u64) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        read += n;
+        // 短く読めるのはファイル末尾だけ。続けて読むとoffsetがアライメントされずEINVALになる
+        if n == 0 || read % DIRECT_IO_ALIGNMENT != 0 {
+            break;
+        }
+    }
+    if read < want {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer"));
+    }
+    dst.copy_from_slice(&buf.as_slice()[head..head + dst.len()]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_direct_write_and_read() {
+        // tmpfsなどO_DIRECTに対応していないファイルシステムでは通常のI/Oになり、
+        // Direct I/Oのアライメントやフォールバックは確かめられない
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("direct");
+
+        // アライメントにもバッファサイズにも揃わない長さ
+        let data: Vec<u8> = (0..DIRECT_WRITE_BUFFER_SIZE + 12345).map(|i| (i % 251) as u8).collect();
+        let file = match create_direct(&path).unwrap() {
+            Some(file) => file,
+            // Direct I/Oに対応していないファイルシステムでも同じように書ける
+            None => File::create(&path).unwrap(),
+        };
+        let mut writer = DirectFileWriter::new(file);
+        for chunk in data.chunks(1000) {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+        writer.sync().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+
+        let file = open_direct(&path).unwrap().unwrap_or_else(|| File::open(&path).unwrap());
+        let mut buf = vec![0u8; 100];
+        let offset = data.len() - 100;
+        read_exact_at_aligned(&file, &mut buf, offset as u64).unwrap();
+        assert_eq!(buf, &data[offset..]);
+
+        // ファイル末尾で短く読めたらUnexpectedEof
+        let mut buf = vec![0u8; 101];
+        let err = read_exact_at_aligned(&file, &mut buf, offset as u64).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let mut buf = vec![0u8; DIRECT_IO_ALIGNMENT * 2];
+        let err = read_exact_at_aligned(&file, &mut buf, (align_up(data.len()) - DIRECT_IO_ALIGNMENT) as u64).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,70 @@
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+/// クレート共通のエラー
+///
+/// 呼び出し側が種類ごとに対処を変えられるよう、
+/// `std::io::Error`に詰め込まずに原因を区別して返す
+#[derive(Debug)]
+pub enum Error {
+    /// ファイル操作の失敗
+    Io(io::Error),
+    /// ディスク上のデータ（OPTIONSファイルなど）が壊れている
+    Corruption(String),
+    /// 引数や設定値が不正
+    InvalidArgument(String),
+    /// 他の処理と競合したため実行できない（再試行できる）
+    Busy(String),
+    /// write stallが起きるため書き込みを断った（`WriteOptions::no_slowdown`のとき）
+    ///
+    /// 書き込みは反映されていない。フラッシュが進めば再試行できる
+    WriteStall,
+    /// バックグラウンドスレッドが終了しており、要求を処理できない
+    ShutdownInProgress,
+    /// バックグラウンドフラッシュが失敗しており、read-onlyモードになっている
+    ///
+    /// 元のエラーは`source()`で取得できる
+    BackgroundError(Arc<Error>),
+    /// 対象のファイルが存在しない（OPTIONSファイルやSSTable）
+    NotFound(String),
+    /// ロックのpoisonやフラッシュスレッドの異常終了
+    ///
+    /// `WritePath::resume`で復帰できない場合は開き直す必要がある
+    Fatal(String),
+}
+
+/// クレート共通のResult
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "IO error: {}", e),
+            Error::Corruption(msg) => write!(f, "Corruption: {}", msg),
+            Error::InvalidArgument(msg) => write!(f, "Invalid argument: {}", msg),
+            Error::Busy(msg) => write!(f, "Resource busy: {}", msg),
+            Error::WriteStall => write!(f, "Write stall: write would wait for flush"),
+            Error::ShutdownInProgress => write!(f, "Shutdown in progress"),
+            Error::BackgroundError(e) => write!(f, "Background flush failed: {}", e),
+            Error::NotFound(msg) => write!(f, "Not found: {}", msg),
+            Error::Fatal(msg) => write!(f, "Fatal error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::BackgroundError(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,96 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::error::Error;
+
+/// WritePathの処理の節目で呼ばれるコールバック（RocksDBのEventListener相当）
+///
+/// `Options::listeners`に登録する。どのメソッドも何もしないデフォルト実装があるので、
+/// 必要なものだけを実装すればよい。
+///
+/// コールバックはイベントが起きたスレッド（書き込み側かフラッシュスレッド）で、
+/// WritePathの内部のロックを解放してから、イベントが起きた順に呼ばれる。
+/// そのため通知はイベントより遅れることがあり、別のスレッドが代わりに通知することもある
+/// （write stallのStoppedは、止まっている書き込みの代わりにフラッシュスレッドが通知することがある）。
+/// 書き込みやフラッシュを遅らせるので、コールバックの中で長くブロックしてはならない
+pub trait EventListener: Send + Sync {
+    /// mutable MemTableがimmutableになり、フラッシュスレッドに渡される直前
+    fn on_memtable_sealed(&self, _info: &MemTableInfo) {}
+
+    /// SSTableの書き出しを始める直前（file_size、num_entries、durationは0）
+    fn on_flush_begin(&self, _info: &FlushJobInfo) {}
+
+    /// SSTableの書き出しが完了した後（失敗した場合はon_background_errorが呼ばれる）
+    fn on_flush_completed(&self, _info: &FlushJobInfo) {}
+
+    /// コンパクションが完了した後（コンパクションはまだないので呼ばれない）
+    fn on_compaction_completed(&self, _info: &CompactionJobInfo) {}
+
+    /// write stallの状態が変わった
+    fn on_stall_conditions_changed(&self, _info: &WriteStallInfo) {}
+
+    /// バックグラウンドエラーを記録してread-onlyモードに入った
+    ///
+    /// 致命的エラーが記録済みで上書きしなかったエラーは通知しない
+    fn on_background_error(&self, _error: &Error) {}
+}
+
+/// Optionsの比較では同じリスナーを共有しているかを見る
+impl PartialEq for dyn EventListener {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(self, other)
+    }
+}
+
+impl fmt::Debug for dyn EventListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EventListener({:p})", self)
+    }
+}
+
+/// immutableになったMemTable
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemTableInfo {
+    /// エントリの数（同じキーへの上書きを含む）
+    pub num_entries: u64,
+    /// キーと値の合計バイト数
+    pub data_size: u64,
+}
+
+/// フラッシュ1回分の情報
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlushJobInfo {
+    /// 書き出すSSTableのパス
+    pub file_path: PathBuf,
+    pub file_number: u64,
+    /// SSTableのサイズ
+    pub file_size: u64,
+    /// SSTableに書き出したキーの数
+    pub num_entries: u64,
+    /// 書き出しにかかった時間
+    pub duration: Duration,
+}
+
+/// コンパクション1回分の情報
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompactionJobInfo {
+    pub input_files: Vec<PathBuf>,
+    pub output_files: Vec<PathBuf>,
+    pub duration: Duration,
+}
+
+/// write stallの状態
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteStallCondition {
+    Normal,
+    /// immutable MemTableの数が上限に達し、書き込みが止まっている
+    Stopped,
+}
+
+/// write stallの状態の変化
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteStallInfo {
+    pub prev: WriteStallCondition,
+    pub cur: WriteStallCondition,
+}
@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::{read_file, write_file, FileLock, FileOptions, FileSystem, MappedFile, RandomAccessFile, WritableFile};
+
+/// 障害を注入するファイルシステム（RocksDBのFaultInjectionTestFS相当）
+///
+/// 別のファイルシステム（通常はMemFileSystem）を包み、書き込みやfsyncを失敗させたり、
+/// 任意の操作の時点で電源断を起こしたりする。
+/// `drop_unsynced_data()`で、fsyncしていないデータとsync_dirしていないディレクトリ操作を
+/// 巻き戻し、電源断から再起動した後のディスクの状態を作る
+#[derive(Clone, Debug)]
+pub struct FaultInjectionFileSystem {
+    inner: Arc<dyn FileSystem>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+#[derive(Debug, Default)]
+struct FaultState {
+    /// 障害を設定してから書き込んだバイト数
+    bytes_written: u64,
+    /// このバイト数を書き込んだ後の書き込みを失敗させる
+    write_error_after: Option<u64>,
+    /// このバイト数を書き込んだ後のfsync（sync_dirを含む）を失敗させる
+    sync_error_after: Option<u64>,
+    /// ファイルの作成と書き込みをENOSPCで失敗させる
+    no_space: bool,
+    /// 電源断までに成功させる操作の数
+    power_cut_after: Option<u64>,
+    /// 電源断の後はすべての操作が失敗する
+    powered_off: bool,
+    /// このファイルシステムで作成したファイルのfsync済みのサイズ
+    synced_len: HashMap<PathBuf, u64>,
+    /// 最後のsync_dir以降のディレクトリ操作（古い順）
+    unsynced_dir_ops: Vec<DirOp>,
+}
+
+/// 電源断で失われうるディレクトリ操作
+#[derive(Debug)]
+enum DirOp {
+    Created(PathBuf),
+    Renamed {
+        from: PathBuf,
+        to: PathBuf,
+        /// 置き換えられたファイルの永続化済みの内容
+        replaced: Option<Vec<u8>>,
+    },
+    Removed {
+        path: PathBuf,
+        /// 削除したファイルの永続化済みの内容
+        data: Vec<u8>,
+    },
+}
+
+impl DirOp {
+    fn dir(&self) -> Option<&Path> {
+        match self {
+            DirOp::Created(path) | DirOp::Renamed { to: path, .. } | DirOp::Removed { path, .. } => {
+                path.parent()
+            }
+        }
+    }
+}
+
+fn injected_error(message: &str) -> io::Error {
+    io::Error::other(format!("injected {}", message))
+}
+
+fn no_space() -> io::Error {
+    io::Error::from_raw_os_error(libc::ENOSPC)
+}
+
+impl FaultState {
+    /// 状態を変える操作の前に呼ぶ（電源断のカウントダウンを進める）
+    fn before_mutation(&mut self) -> io::Result<()> {
+        self.check_power()?;
+        match self.power_cut_after {
+            Some(0) => {
+                self.powered_off = true;
+                Err(injected_error("power cut"))
+            }
+            Some(n) => {
+                self.power_cut_after = Some(n - 1);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn check_power(&self) -> io::Result<()> {
+        match self.powered_off {
+            true => Err(injected_error("power cut")),
+            false => Ok(()),
+        }
+    }
+}
+
+impl FaultInjectionFileSystem {
+    pub fn new(inner: Arc<dyn FileSystem>) -> Self {
+        Self {
+            inner,
+            state: Arc::new(Mutex::new(FaultState::default())),
+        }
+    }
+
+    /// これからbytesバイトを書き込んだ後の書き込みを失敗させる
+    pub fn fail_writes_after(&self, bytes: u64) {
+        let mut state = self.lock();
+        state.bytes_written = 0;
+        state.write_error_after = Some(bytes);
+    }
+
+    /// これからbytesバイトを書き込んだ後のfsync（sync_dirを含む）を失敗させる
+    pub fn fail_syncs_after(&self, bytes: u64) {
+        let mut state = self.lock();
+        state.bytes_written = 0;
+        state.sync_error_after = Some(bytes);
+    }
+
+    /// ファイルの作成と書き込みをENOSPCで失敗させる
+    pub fn set_no_space(&self, no_space: bool) {
+        self.lock().no_space = no_space;
+    }
+
+    /// ops回の操作（作成、書き込み、fsync、rename、削除）の後に電源断を起こす
+    ///
+    /// 0なら次の操作で電源断になる
+    pub fn power_cut_after(&self, ops: u64) {
+        self.lock().power_cut_after = Some(ops);
+    }
+
+    pub fn is_powered_off(&self) -> bool {
+        self.lock().powered_off
+    }
+
+    /// 書き込み、fsync、ENOSPCの障害を解除する（電源断は`drop_unsynced_data()`まで続く）
+    pub fn clear_faults(&self) {
+        let mut state = self.lock();
+        state.write_error_after = None;
+        state.sync_error_after = None;
+        state.no_space = false;
+        state.power_cut_after = None;
+    }
+
+    /// 電源断から再起動した後の状態にする
+    ///
+    /// fsyncしていないデータを捨て、sync_dirしていない作成、rename、削除を巻き戻してから、
+    /// すべての障害を解除する。開いているWritePathは先に破棄しておく
+    pub fn drop_unsynced_data(&self) -> io::Result<()> {
+        let mut state = self.lock();
+        let fs = &*self.inner;
+
+        for (path, &len) in &state.synced_len {
+            match read_file(fs, path) {
+                Ok(data) if data.len() as u64 > len => write_file(fs, path, &data[..len as usize])?,
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        for op in std::mem::take(&mut state.unsynced_dir_ops).into_iter().rev() {
+            match op {
+                DirOp::Created(path) => match fs.remove_file(&path) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                },
+                DirOp::Renamed { from, to, replaced } => {
+                    fs.rename(&to, &from)?;
+                    if let Some(data) = replaced {
+                        write_file(fs, &to, &data)?;
+                    }
+                }
+                DirOp::Removed { path, data } => write_file(fs, &path, &data)?,
+            }
+        }
+
+        *state = FaultState::default();
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, FaultState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// 電源断で残る内容（作成したファイルはfsync済みの範囲だけ）
+    fn durable_contents(&self, state: &FaultState, path: &Path) -> io::Result<Option<Vec<u8>>> {
+        match read_file(&*self.inner, path) {
+            Ok(mut data) => {
+                if let Some(&len) = state.synced_len.get(path) {
+                    data.truncate(len as usize);
+                }
+                Ok(Some(data))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl FileSystem for FaultInjectionFileSystem {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.lock().before_mutation()?;
+        self.inner.create_dir_all(path)
+    }
+
+    fn new_writable_file(&self, path: &Path, options: &FileOptions) -> io::Result<Box<dyn WritableFile>> {
+        let mut state = self.lock();
+        state.before_mutation()?;
+        if state.no_space {
+            return Err(no_space());
+        }
+        let replaced = self.durable_contents(&state, path)?;
+        let file = self.inner.new_writable_file(path, options)?;
+
+        // 既存のファイルの切り詰めは、削除と作成として巻き戻す
+        if let Some(data) = replaced {
+            state.unsynced_dir_ops.push(DirOp::Removed {
+                path: path.to_path_buf(),
+                data,
+            });
+        }
+        state.unsynced_dir_ops.push(DirOp::Created(path.to_path_buf()));
+        state.synced_len.insert(path.to_path_buf(), 0);
+        Ok(Box::new(FaultWritableFile {
+            inner: file,
+            path: path.to_path_buf(),
+            written: 0,
+            state: Arc::clone(&self.state),
+        }))
+    }
+
+    fn new_random_access_file(
+        &self,
+        path: &Path,
+        options: &FileOptions,
+    ) -> io::Result<Box<dyn RandomAccessFile>> {
+        self.lock().check_power()?;
+        self.inner.new_random_access_file(path, options)
+    }
+
+    fn map_file(&self, path: &Path) -> io::Result<Option<MappedFile>> {
+        self.lock().check_power()?;
+        self.inner.map_file(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.lock();
+        state.before_mutation()?;
+        let replaced = self.durable_contents(&state, to)?;
+        self.inner.rename(from, to)?;
+
+        if let Some(len) = state.synced_len.remove(from) {
+            state.synced_len.insert(to.to_path_buf(), len);
+        } else {
+            state.synced_len.remove(to);
+        }
+        state.unsynced_dir_ops.push(DirOp::Renamed {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            replaced,
+        });
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.lock();
+        state.before_mutation()?;
+        let data = self.durable_contents(&state, path)?;
+        self.inner.remove_file(path)?;
+
+        state.synced_len.remove(path);
+        if let Some(data) = data {
+            state.unsynced_dir_ops.push(DirOp::Removed {
+                path: path.to_path_buf(),
+                data,
+            });
+        }
+        Ok(())
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+        self.lock().check_power()?;
+        self.inner.list_dir(path)
+    }
+
+    fn sync_file(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.lock();
+        state.before_mutation()?;
+        if state.sync_error_after.is_some_and(|limit| state.bytes_written >= limit) {
+            return Err(injected_error("sync error"));
+        }
+        self.inner.sync_file(path)?;
+        if let Some(len) = state.synced_len.get_mut(path) {
+            *len = self.inner.new_random_access_file(path, &FileOptions::default())?.size()?;
+        }
+        Ok(())
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.lock();
+        state.before_mutation()?;
+        if state.sync_error_after.is_some_and(|limit| state.bytes_written >= limit) {
+            return Err(injected_error("sync error"));
+        }
+        self.inner.sync_dir(path)?;
+        state.unsynced_dir_ops.retain(|op| op.dir() != Some(path));
+        Ok(())
+    }
+
+    fn lock_file(&self, path: &Path) -> io::Result<Box<dyn FileLock>> {
+        self.lock().check_power()?;
+        self.inner.lock_file(path)
+    }
+}
+
+struct FaultWritableFile {
+    inner: Box<dyn WritableFile>,
+    path: PathBuf,
+    /// このファイルに書き込んだバイト数
+    written: u64,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl FaultWritableFile {
+    fn lock(&self) -> MutexGuard<'_, FaultState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Write for FaultWritableFile {
+    /// 失敗させるまでの残りが足りなければ、書ける分だけ書く
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let state = Arc::clone(&self.state);
+        let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.before_mutation()?;
+        if state.no_space {
+            return Err(no_space());
+        }
+        let mut len = buf.len();
+        if let Some(limit) = state.write_error_after {
+            let remaining = limit.saturating_sub(state.bytes_written);
+            if remaining == 0 {
+                return Err(injected_error("write error"));
+            }
+            len = len.min(remaining as usize);
+        }
+
+        let n = self.inner.write(&buf[..len])?;
+        state.bytes_written += n as u64;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().check_power()?;
+        self.inner.flush()
+    }
+}
+
+impl WritableFile for FaultWritableFile {
+    fn finish(&mut self) -> io::Result<()> {
+        self.lock().before_mutation()?;
+        self.inner.finish()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        let state = Arc::clone(&self.state);
+        let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.before_mutation()?;
+        if state.sync_error_after.is_some_and(|limit| state.bytes_written >= limit) {
+            return Err(injected_error("sync error"));
+        }
+        self.inner.sync()?;
+        if let Some(len) = state.synced_len.get_mut(&self.path) {
+            *len = self.written;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_system::MemFileSystem;
+
+    fn append(fs: &FaultInjectionFileSystem, path: &Path, data: &[u8], sync: bool) -> io::Result<()> {
+        let mut file = fs.new_writable_file(path, &FileOptions::default())?;
+        file.write_all(data)?;
+        file.finish()?;
+        if sync {
+            file.sync()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_unsynced_data() {
+        let mem = MemFileSystem::new();
+        let fs = FaultInjectionFileSystem::new(Arc::new(mem.clone()));
+        let dir = Path::new("/db");
+        fs.create_dir_all(dir).unwrap();
+
+        // fsyncしてsync_dirしたファイルだけが残る
+        append(&fs, &dir.join("synced"), b"synced", true).unwrap();
+        fs.sync_dir(dir).unwrap();
+        append(&fs, &dir.join("no_sync.tmp"), b"data", false).unwrap();
+        fs.rename(&dir.join("no_sync.tmp"), &dir.join("no_sync")).unwrap();
+        fs.sync_dir(dir).unwrap();
+        append(&fs, &dir.join("no_dir_sync"), b"data", true).unwrap();
+        fs.remove_file(&dir.join("synced")).unwrap();
+
+        fs.drop_unsynced_data().unwrap();
+        let mut names = mem.list_dir(dir).unwrap();
+        names.sort();
+        assert_eq!(names, vec!["no_sync", "synced"]);
+        assert_eq!(read_file(&mem, &dir.join("synced")).unwrap(), b"synced");
+        assert!(read_file(&mem, &dir.join("no_sync")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_write_errors_and_power_cut() {
+        let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+        let dir = Path::new("/db");
+        fs.create_dir_all(dir).unwrap();
+
+        fs.fail_writes_after(4);
+        assert!(append(&fs, &dir.join("a"), b"hello", false).is_err());
+        fs.clear_faults();
+        fs.set_no_space(true);
+        let err = append(&fs, &dir.join("a"), b"hello", false).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOSPC));
+        fs.clear_faults();
+
+        // 作成と書き込みの後の操作で電源断
+        fs.power_cut_after(2);
+        assert!(append(&fs, &dir.join("b"), b"hello", true).is_err());
+        assert!(fs.is_powered_off());
+        assert!(fs.list_dir(dir).is_err());
+        fs.drop_unsynced_data().unwrap();
+        assert!(!fs.is_powered_off());
+        assert!(fs.list_dir(dir).unwrap().is_empty());
+    }
+}
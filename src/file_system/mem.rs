@@ -0,0 +1,247 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock};
+
+use super::{FileLock, FileOptions, FileSystem, RandomAccessFile, WritableFile};
+
+/// ファイルの中身（開いているファイルと共有する）
+type FileData = Arc<RwLock<Vec<u8>>>;
+
+/// メモリ上のファイルシステム（テスト用）
+///
+/// ディスクに触れないので速く、結果が環境に左右されない。
+/// cloneしたものは同じファイルを共有する。
+/// 削除や置き換えをしても、開いているファイルは元の中身を読み書きできる（POSIXと同じ）
+#[derive(Clone, Debug, Default)]
+pub struct MemFileSystem {
+    state: Arc<Mutex<MemState>>,
+}
+
+#[derive(Debug, Default)]
+struct MemState {
+    dirs: HashSet<PathBuf>,
+    files: HashMap<PathBuf, FileData>,
+    locked: HashSet<PathBuf>,
+}
+
+impl MemFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MemState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl MemState {
+    /// 親ディレクトリがなければNotFound
+    fn check_parent(&self, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() && !self.dirs.contains(parent) => {
+                Err(not_found(parent))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn file(&self, path: &Path) -> io::Result<FileData> {
+        self.files.get(path).cloned().ok_or_else(|| not_found(path))
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{}: no such file or directory", path.display()))
+}
+
+impl FileSystem for MemFileSystem {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.lock();
+        for dir in path.ancestors().filter(|dir| !dir.as_os_str().is_empty()) {
+            if state.files.contains_key(dir) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is a file", dir.display()),
+                ));
+            }
+            state.dirs.insert(dir.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn new_writable_file(&self, path: &Path, _options: &FileOptions) -> io::Result<Box<dyn WritableFile>> {
+        let mut state = self.lock();
+        state.check_parent(path)?;
+        let data = FileData::default();
+        state.files.insert(path.to_path_buf(), Arc::clone(&data));
+        Ok(Box::new(MemWritableFile { data }))
+    }
+
+    fn new_random_access_file(
+        &self,
+        path: &Path,
+        _options: &FileOptions,
+    ) -> io::Result<Box<dyn RandomAccessFile>> {
+        let data = self.lock().file(path)?;
+        Ok(Box::new(MemRandomAccessFile { data }))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.lock();
+        state.check_parent(to)?;
+        let data = state.files.remove(from).ok_or_else(|| not_found(from))?;
+        state.files.insert(to.to_path_buf(), data);
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.lock().files.remove(path).map(|_| ()).ok_or_else(|| not_found(path))
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+        let state = self.lock();
+        if !state.dirs.contains(path) {
+            return Err(not_found(path));
+        }
+        let names = state
+            .files
+            .keys()
+            .chain(state.dirs.iter())
+            .filter(|entry| entry.parent() == Some(path))
+            .filter_map(|entry| entry.file_name()?.to_str().map(str::to_string))
+            .collect();
+        Ok(names)
+    }
+
+    /// メモリ上のファイルは常に永続化されている扱い
+    fn sync_file(&self, path: &Path) -> io::Result<()> {
+        self.lock().file(path).map(|_| ())
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        match self.lock().dirs.contains(path) {
+            true => Ok(()),
+            false => Err(not_found(path)),
+        }
+    }
+
+    fn lock_file(&self, path: &Path) -> io::Result<Box<dyn FileLock>> {
+        let mut state = self.lock();
+        state.check_parent(path)?;
+        if !state.locked.insert(path.to_path_buf()) {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("{} is already locked", path.display()),
+            ));
+        }
+        state.files.entry(path.to_path_buf()).or_default();
+        Ok(Box::new(MemFileLock {
+            fs: self.clone(),
+            path: path.to_path_buf(),
+        }))
+    }
+}
+
+struct MemWritableFile {
+    data: FileData,
+}
+
+impl Write for MemWritableFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.write().unwrap_or_else(PoisonError::into_inner).extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WritableFile for MemWritableFile {
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct MemRandomAccessFile {
+    data: FileData,
+}
+
+impl RandomAccessFile for MemRandomAccessFile {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let data = self.data.read().unwrap_or_else(PoisonError::into_inner);
+        let src = (offset as usize)
+            .checked_add(buf.len())
+            .and_then(|end| data.get(offset as usize..end))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer"))?;
+        buf.copy_from_slice(src);
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.data.read().unwrap_or_else(PoisonError::into_inner).len() as u64)
+    }
+}
+
+#[derive(Debug)]
+struct MemFileLock {
+    fs: MemFileSystem,
+    path: PathBuf,
+}
+
+impl FileLock for MemFileLock {}
+
+impl Drop for MemFileLock {
+    fn drop(&mut self) {
+        self.fs.lock().locked.remove(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_system::{read_file, write_file};
+
+    #[test]
+    fn test_files_and_directories() {
+        let fs = MemFileSystem::new();
+        let dir = Path::new("/db");
+
+        // ディレクトリがなければ作成できない
+        let err = fs.new_writable_file(&dir.join("a"), &FileOptions::default()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        fs.create_dir_all(dir).unwrap();
+        write_file(&fs, &dir.join("a.tmp"), b"hello").unwrap();
+        fs.rename(&dir.join("a.tmp"), &dir.join("a")).unwrap();
+        assert_eq!(read_file(&fs, &dir.join("a")).unwrap(), b"hello");
+        assert_eq!(fs.list_dir(dir).unwrap(), vec!["a".to_string()]);
+
+        // 削除しても開いているファイルは読める
+        let file = fs.new_random_access_file(&dir.join("a"), &FileOptions::default()).unwrap();
+        fs.remove_file(&dir.join("a")).unwrap();
+        let mut buf = [0u8; 3];
+        file.read_exact_at(&mut buf, 2).unwrap();
+        assert_eq!(&buf, b"llo");
+        assert!(file.read_exact_at(&mut buf, 3).is_err());
+        assert!(fs.list_dir(dir).unwrap().is_empty());
+        assert_eq!(fs.remove_file(&dir.join("a")).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_lock_file() {
+        let fs = MemFileSystem::new();
+        fs.create_dir_all(Path::new("/db")).unwrap();
+        let path = Path::new("/db/LOCK");
+
+        let lock = fs.lock_file(path).unwrap();
+        assert_eq!(fs.lock_file(path).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        drop(lock);
+        fs.lock_file(path).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+//! ファイル操作の抽象化（RocksDBのFileSystem/Env相当）
+//!
+//! WritePathやTableReaderはstd::fsを直接使わず、`FileSystem`を通してファイルを扱う。
+//! 通常は`PosixFileSystem`を使い、テストでは`MemFileSystem`や
+//! 障害を注入する`FaultInjectionFileSystem`に差し替えられる
+
+mod fault_injection;
+mod mem;
+mod posix;
+
+use std::fmt::Debug;
+use std::io::{self, Write};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+
+pub use fault_injection::FaultInjectionFileSystem;
+pub use mem::MemFileSystem;
+pub use posix::PosixFileSystem;
+
+/// ファイルを開くときの設定
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileOptions {
+    /// ページキャッシュを通さずに読む（対応していなければ通常の読み込み）
+    pub use_direct_reads: bool,
+    /// ページキャッシュを通さずに書く（対応していなければ通常の書き込み）
+    pub use_direct_writes: bool,
+    /// io_uringで書く（`io_uring` featureのときのみ。Direct I/Oが優先される）
+    pub use_io_uring: bool,
+    /// この量を書き込むたびに書き込み済みの範囲をディスクへ書き出し始める（0なら無効）
+    pub bytes_per_sync: u64,
+}
+
+/// 書き込み用のファイル（RocksDBのFSWritableFile相当）
+///
+/// 先頭から順に書き込む。PosixFileSystemでは`BufWriter<FileWriter>`で、
+/// 設定に応じてDirect I/Oやio_uringの実装になる
+pub trait WritableFile: Write + Send {
+    /// バッファや書き込み中のデータをすべてファイルに書き出す
+    fn finish(&mut self) -> io::Result<()>;
+
+    /// 書き出した内容をディスクに永続化する（finish()の後に呼ぶ）
+    fn sync(&mut self) -> io::Result<()>;
+}
+
+/// 任意の位置から読むファイル（RocksDBのFSRandomAccessFile相当）
+pub trait RandomAccessFile: Send + Sync {
+    /// offsetからbufの長さだけ読む（足りなければUnexpectedEof）
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+
+    /// ファイルのサイズ
+    fn size(&self) -> io::Result<u64>;
+}
+
+/// メモリにマップしたファイルの内容
+///
+/// ファイルが削除されても、最後の参照がなくなるまで読める
+pub type MappedFile = Arc<dyn Deref<Target = [u8]> + Send + Sync>;
+
+/// ファイルのロック（dropで解放される）
+pub trait FileLock: Send + Sync + Debug {}
+
+/// ファイル操作
+///
+/// パスは呼び出し側がdata_dirからjoinしたものをそのまま渡す
+pub trait FileSystem: Send + Sync + Debug {
+    /// ディレクトリを（親も含めて）作成する
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// 書き込み用にファイルを作成する（既にあれば切り詰める）
+    fn new_writable_file(&self, path: &Path, options: &FileOptions) -> io::Result<Box<dyn WritableFile>>;
+
+    /// 読み込み用にファイルを開く
+    fn new_random_access_file(
+        &self,
+        path: &Path,
+        options: &FileOptions,
+    ) -> io::Result<Box<dyn RandomAccessFile>>;
+
+    /// ファイルをメモリにマップする（対応していなければNone）
+    fn map_file(&self, _path: &Path) -> io::Result<Option<MappedFile>> {
+        Ok(None)
+    }
+
+    /// ファイルの名前を変える（toが既にあれば置き換える）
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// ディレクトリ直下のエントリの名前
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<String>>;
+
+    /// ファイルの内容をディスクに永続化する
+    fn sync_file(&self, path: &Path) -> io::Result<()>;
+
+    /// ディレクトリのエントリ（作成、rename、削除）をディスクに永続化する
+    fn sync_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// ファイルを（なければ作成して）排他ロックする
+    ///
+    /// 既にロックされていれば`io::ErrorKind::WouldBlock`を返す
+    fn lock_file(&self, path: &Path) -> io::Result<Box<dyn FileLock>>;
+}
+
+/// デフォルトのファイルシステム
+pub fn default_file_system() -> Arc<dyn FileSystem> {
+    Arc::new(PosixFileSystem)
+}
+
+/// ファイル全体を読む
+pub(crate) fn read_file(fs: &dyn FileSystem, path: &Path) -> io::Result<Vec<u8>> {
+    let file = fs.new_random_access_file(path, &FileOptions::default())?;
+    let mut buf = vec![0u8; file.size()? as usize];
+    file.read_exact_at(&mut buf, 0)?;
+    Ok(buf)
+}
+
+/// ファイル全体を書いてfsyncする
+pub(crate) fn write_file(fs: &dyn FileSystem, path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = fs.new_writable_file(path, &FileOptions::default())?;
+    file.write_all(data)?;
+    file.finish()?;
+    file.sync()
+}
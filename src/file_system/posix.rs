@@ -0,0 +1,131 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use memmap2::Mmap;
+
+use super::{FileLock, FileOptions, FileSystem, MappedFile, RandomAccessFile, WritableFile};
+use crate::direct_io::{create_direct, open_direct, read_exact_at_aligned, DirectFileWriter};
+use crate::file_writer::FileWriter;
+
+/// std::fsによる実装
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PosixFileSystem;
+
+impl FileSystem for PosixFileSystem {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    /// Direct I/Oやio_uringを使えない環境では通常のBufWriter<FileWriter>になる
+    fn new_writable_file(&self, path: &Path, options: &FileOptions) -> io::Result<Box<dyn WritableFile>> {
+        if options.use_direct_writes {
+            if let Some(file) = create_direct(path)? {
+                return Ok(Box::new(DirectFileWriter::new(file)));
+            }
+        }
+
+        let file = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+
+        #[cfg(all(feature = "io_uring", target_os = "linux"))]
+        if options.use_io_uring {
+            if let Ok(writer) = crate::uring::UringFileWriter::new(file.try_clone()?, options.bytes_per_sync) {
+                return Ok(Box::new(writer));
+            }
+        }
+
+        // BufWriterでバッファリング（デフォルト8KB）
+        Ok(Box::new(BufWriter::new(FileWriter::new(file, options.bytes_per_sync))))
+    }
+
+    fn new_random_access_file(
+        &self,
+        path: &Path,
+        options: &FileOptions,
+    ) -> io::Result<Box<dyn RandomAccessFile>> {
+        if options.use_direct_reads {
+            if let Some(file) = open_direct(path)? {
+                return Ok(Box::new(PosixRandomAccessFile { file, direct: true }));
+            }
+        }
+        Ok(Box::new(PosixRandomAccessFile {
+            file: File::open(path)?,
+            direct: false,
+        }))
+    }
+
+    fn map_file(&self, path: &Path) -> io::Result<Option<MappedFile>> {
+        let file = File::open(path)?;
+        // SAFETY: SSTableはrenameで公開された後は書き換えも切り詰めもされない
+        Ok(Some(Arc::new(unsafe { Mmap::map(&file)? })))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(path)? {
+            // UTF-8でない名前は自分で作ったファイルではないので無視する
+            if let Ok(name) = entry?.file_name().into_string() {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    fn sync_file(&self, path: &Path) -> io::Result<()> {
+        File::open(path)?.sync_all()
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        File::open(path)?.sync_all()
+    }
+
+    /// flockで排他ロックする（同じプロセス内の別のファイルディスクリプタとも競合する）
+    fn lock_file(&self, path: &Path) -> io::Result<Box<dyn FileLock>> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Box::new(PosixFileLock { _file: file })),
+            Err(fs::TryLockError::WouldBlock) => Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("{} is locked by another process", path.display()),
+            )),
+            Err(fs::TryLockError::Error(e)) => Err(e),
+        }
+    }
+}
+
+struct PosixRandomAccessFile {
+    file: File,
+    /// Direct I/Oで開いたファイル（アライメントして読む）
+    direct: bool,
+}
+
+impl RandomAccessFile for PosixRandomAccessFile {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        match self.direct {
+            true => read_exact_at_aligned(&self.file, buf, offset),
+            false => self.file.read_exact_at(buf, offset),
+        }
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+}
+
+/// ファイルを閉じるとロックも解放される
+#[derive(Debug)]
+struct PosixFileLock {
+    _file: File,
+}
+
+impl FileLock for PosixFileLock {}
@@ -0,0 +1,331 @@
+//! data_dirのLOGファイル（RocksDBのinfo LOG相当）
+//!
+//! 開いたときの設定、フラッシュの結果、write stall、バックグラウンドエラー、
+//! 定期的な統計を1行ずつ書き出す。LOGへの書き込みに失敗しても書き込みパスは止めない
+
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::error::Error;
+use crate::event_listener::{
+    CompactionJobInfo, EventListener, FlushJobInfo, MemTableInfo, WriteStallCondition, WriteStallInfo,
+};
+use crate::file_system::{FileOptions, FileSystem, WritableFile};
+use crate::options::Options;
+use crate::statistics::Statistics;
+
+/// 現在のLOGファイルの名前
+pub(crate) const LOG_FILE_NAME: &str = "LOG";
+
+/// ローテーションした古いLOGファイルの名前の接頭辞（後ろにマイクロ秒単位の時刻が付く）
+const OLD_LOG_PREFIX: &str = "LOG.old.";
+
+const MB: f64 = 1024.0 * 1024.0;
+
+struct LogFile {
+    writer: Box<dyn WritableFile>,
+    size: u64,
+}
+
+/// data_dirのLOGファイルに書き出すロガー
+///
+/// EventListenerとしてWritePathに登録し、フラッシュやwrite stallを記録する
+pub(crate) struct InfoLog {
+    fs: Arc<dyn FileSystem>,
+    data_dir: PathBuf,
+    max_log_file_size: u64,
+    keep_log_file_num: usize,
+    /// LOGファイルを作れなかった場合はNone（何も記録しない）
+    file: Mutex<Option<LogFile>>,
+    opened_at: Instant,
+    /// 開いてからMemTableとしてフラッシュに渡したバイト数
+    ingested_bytes: AtomicU64,
+    /// 開いてからフラッシュで書き出したバイト数
+    flushed_bytes: AtomicU64,
+}
+
+impl InfoLog {
+    /// 既存のLOGをローテーションして新しいLOGを作る
+    ///
+    /// 作れなかった場合は何も記録しないロガーになる（WritePathは開ける）
+    pub(crate) fn open(fs: Arc<dyn FileSystem>, data_dir: &Path, options: &Options) -> Self {
+        let log = Self {
+            fs,
+            data_dir: data_dir.to_path_buf(),
+            max_log_file_size: options.max_log_file_size,
+            keep_log_file_num: options.keep_log_file_num,
+            file: Mutex::new(None),
+            opened_at: Instant::now(),
+            ingested_bytes: AtomicU64::new(0),
+            flushed_bytes: AtomicU64::new(0),
+        };
+        *log.lock_file() = log.rotate();
+        log
+    }
+
+    fn lock_file(&self) -> std::sync::MutexGuard<'_, Option<LogFile>> {
+        self.file.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// 今のLOGを`LOG.old.<時刻>`にして新しいLOGを作り、古いものを消す
+    fn rotate(&self) -> Option<LogFile> {
+        let log_path = self.data_dir.join(LOG_FILE_NAME);
+        let names = self.fs.list_dir(&self.data_dir).ok()?;
+        if names.iter().any(|name| name == LOG_FILE_NAME) {
+            // 同じ時刻に続けてローテーションしても上書きしない
+            let mut micros = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros();
+            while names.contains(&format!("{}{}", OLD_LOG_PREFIX, micros)) {
+                micros += 1;
+            }
+            let old_path = self.data_dir.join(format!("{}{}", OLD_LOG_PREFIX, micros));
+            self.fs.rename(&log_path, &old_path).ok()?;
+        }
+        self.remove_old_logs();
+        let writer = self.fs.new_writable_file(&log_path, &FileOptions::default()).ok()?;
+        Some(LogFile { writer, size: 0 })
+    }
+
+    /// keep_log_file_numを超える古いLOGを古い順に消す
+    fn remove_old_logs(&self) {
+        let Ok(names) = self.fs.list_dir(&self.data_dir) else {
+            return;
+        };
+        let mut old_logs: Vec<(u128, String)> = names
+            .into_iter()
+            .filter_map(|name| Some((name.strip_prefix(OLD_LOG_PREFIX)?.parse().ok()?, name)))
+            .collect();
+        old_logs.sort();
+        let excess = old_logs.len().saturating_sub(self.keep_log_file_num);
+        for (_, name) in &old_logs[..excess] {
+            let _ = self.fs.remove_file(&self.data_dir.join(name));
+        }
+    }
+
+    /// 時刻とスレッドを付けて1行書き出す
+    pub(crate) fn log(&self, args: fmt::Arguments<'_>) {
+        let line = format!("{} {:?} {}\n", format_timestamp(SystemTime::now()), thread::current().id(), args);
+        let mut file = self.lock_file();
+        if self.max_log_file_size > 0 && file.as_ref().is_some_and(|f| f.size >= self.max_log_file_size) {
+            if let Some(mut old) = file.take() {
+                let _ = old.writer.finish();
+            }
+            *file = self.rotate();
+        }
+        if let Some(f) = file.as_mut() {
+            // 失敗しても次の行は書いてみる
+            if f.writer.write_all(line.as_bytes()).is_ok() && f.writer.flush().is_ok() {
+                f.size += line.len() as u64;
+            }
+        }
+    }
+
+    /// 設定を1項目1行で書き出す
+    pub(crate) fn log_options(&self, options: &Options) {
+        for line in options.to_options_string().lines().filter(|line| !line.starts_with('#')) {
+            self.log(format_args!("Options.{}", line));
+        }
+        self.log(format_args!("Options.rate_limiter={}", options.rate_limiter.is_some()));
+        self.log(format_args!("Options.statistics={}", options.statistics.is_some()));
+        self.log(format_args!("Options.listeners={}", options.listeners.len()));
+    }
+
+    /// レベルごとのファイル数とサイズ、書き込み増幅、統計を書き出す
+    ///
+    /// フラッシュの出力しかないので、レベルはL0だけ
+    pub(crate) fn dump_stats(&self, statistics: Option<&Statistics>) {
+        let (mut files, mut bytes) = (0u64, 0u64);
+        for name in self.fs.list_dir(&self.data_dir).unwrap_or_default() {
+            if !name.ends_with(".sst") {
+                continue;
+            }
+            let size = self
+                .fs
+                .new_random_access_file(&self.data_dir.join(&name), &FileOptions::default())
+                .and_then(|file| file.size());
+            if let Ok(size) = size {
+                files += 1;
+                bytes += size;
+            }
+        }
+        let ingested = self.ingested_bytes.load(Ordering::Relaxed);
+        let flushed = self.flushed_bytes.load(Ordering::Relaxed);
+        let write_amp = if ingested == 0 { 0.0 } else { flushed as f64 / ingested as f64 };
+
+        self.log(format_args!("------- DUMPING STATS -------"));
+        self.log(format_args!("Uptime(secs): {:.1}", self.opened_at.elapsed().as_secs_f64()));
+        self.log(format_args!("Level  Files  Size(MB)  W-Amp"));
+        for level in ["L0", "Sum"] {
+            self.log(format_args!("{:<5}  {:>5}  {:>8.2}  {:>5.2}", level, files, bytes as f64 / MB, write_amp));
+        }
+        self.log(format_args!(
+            "Flush since open: ingested {:.2} MB, written {:.2} MB",
+            ingested as f64 / MB,
+            flushed as f64 / MB
+        ));
+        if let Some(statistics) = statistics {
+            for line in statistics.to_string().lines() {
+                self.log(format_args!("STATISTICS: {}", line));
+            }
+        }
+    }
+}
+
+impl EventListener for InfoLog {
+    fn on_memtable_sealed(&self, info: &MemTableInfo) {
+        self.ingested_bytes.fetch_add(info.data_size, Ordering::Relaxed);
+    }
+
+    fn on_flush_begin(&self, info: &FlushJobInfo) {
+        self.log(format_args!("[flush] started: file #{}", info.file_number));
+    }
+
+    fn on_flush_completed(&self, info: &FlushJobInfo) {
+        self.flushed_bytes.fetch_add(info.file_size, Ordering::Relaxed);
+        self.log(format_args!(
+            "[flush] finished: file #{} {} bytes {} keys in {} us",
+            info.file_number,
+            info.file_size,
+            info.num_entries,
+            info.duration.as_micros()
+        ));
+    }
+
+    fn on_compaction_completed(&self, info: &CompactionJobInfo) {
+        self.log(format_args!(
+            "[compaction] finished: {} input files, {} output files in {} us",
+            info.input_files.len(),
+            info.output_files.len(),
+            info.duration.as_micros()
+        ));
+    }
+
+    fn on_stall_conditions_changed(&self, info: &WriteStallInfo) {
+        let message = match info.cur {
+            WriteStallCondition::Stopped => "stopping writes: too many immutable memtables",
+            WriteStallCondition::Normal => "writes resumed",
+        };
+        self.log(format_args!("[stall] {}", message));
+    }
+
+    fn on_background_error(&self, error: &Error) {
+        self.log(format_args!("[error] background error: {}", error));
+    }
+}
+
+/// 一定の間隔でLOGに統計を書き出すスレッド（dropで止まる）
+pub(crate) struct StatsDumper {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StatsDumper {
+    pub(crate) fn start(info_log: Arc<InfoLog>, period: Duration, statistics: Option<Arc<Statistics>>) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                let (lock, cv) = &*stop;
+                let mut stopped = lock.lock().unwrap_or_else(PoisonError::into_inner);
+                loop {
+                    let (guard, timeout) = cv
+                        .wait_timeout_while(stopped, period, |stopped| !*stopped)
+                        .unwrap_or_else(PoisonError::into_inner);
+                    stopped = guard;
+                    if !timeout.timed_out() {
+                        return;
+                    }
+                    info_log.dump_stats(statistics.as_deref());
+                }
+            })
+        };
+        Self {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for StatsDumper {
+    fn drop(&mut self) {
+        let (lock, cv) = &*self.stop;
+        *lock.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        cv.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// UTCの`YYYY/MM/DD-hh:mm:ss.uuuuuu`
+fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // 1970-01-01からの日数を年月日にする（Howard Hinnantのcivil_from_days）
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{:04}/{:02}/{:02}-{:02}:{:02}:{:02}.{:06}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_micros()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_system::{read_file, MemFileSystem};
+
+    fn read_log(fs: &MemFileSystem, path: &str) -> String {
+        String::from_utf8(read_file(fs, Path::new(path)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        let time = UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456);
+        assert_eq!(format_timestamp(time), "2023/11/14-22:13:20.123456");
+        assert_eq!(format_timestamp(UNIX_EPOCH + Duration::from_secs(951_782_400)), "2000/02/29-00:00:00.000000");
+    }
+
+    #[test]
+    fn test_rotation() {
+        let fs = MemFileSystem::new();
+        fs.create_dir_all(Path::new("/db")).unwrap();
+        let options = Options {
+            max_log_file_size: 100,
+            keep_log_file_num: 2,
+            ..Options::default()
+        };
+
+        let log = InfoLog::open(Arc::new(fs.clone()), Path::new("/db"), &options);
+        log.log(format_args!("first"));
+        assert!(read_log(&fs, "/db/LOG").ends_with(" first\n"));
+
+        // 上限を超えるたびにローテーションし、古いLOGはkeep_log_file_numだけ残す
+        for i in 0..10 {
+            log.log(format_args!("line {:0>80}", i));
+        }
+        let old_logs = fs.list_dir(Path::new("/db")).unwrap();
+        assert_eq!(old_logs.iter().filter(|name| name.starts_with(OLD_LOG_PREFIX)).count(), 2);
+        assert!(read_log(&fs, "/db/LOG").ends_with(&format!("line {:0>80}\n", 9)));
+    }
+}
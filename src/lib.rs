@@ -1,4 +1,38 @@
+// ファイルの読み書きにstd::os::unix（pread/pwrite、flock、O_DIRECT）を使う
+#[cfg(not(unix))]
+compile_error!("this crate supports only Unix-like platforms (Linux and macOS)");
+
+mod direct_io;
+pub mod error;
+pub mod event_listener;
+pub mod file_system;
+mod file_writer;
+mod info_log;
+mod lru;
+pub mod metrics;
+#[cfg(feature = "metrics_http")]
+pub mod metrics_http;
+pub mod options;
+pub mod perf_context;
+pub mod rate_limiter;
+pub mod sstable;
+pub mod statistics;
+pub mod table_cache;
+mod trace;
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+mod uring;
 pub mod write_path;
 pub mod write_path_skiplist;
 
-pub use write_path::WritePath;
+pub use error::{Error, Result};
+pub use event_listener::{
+    CompactionJobInfo, EventListener, FlushJobInfo, MemTableInfo, WriteStallCondition, WriteStallInfo,
+};
+pub use file_system::{FaultInjectionFileSystem, FileSystem, MemFileSystem, PosixFileSystem};
+pub use options::{CompactionStyle, Options, OptionsBuilder, SyncPolicy};
+pub use perf_context::{perf_context, reset_perf_context, set_perf_level, PerfContext, PerfLevel};
+pub use rate_limiter::{IoPriority, RateLimiter};
+pub use sstable::{BlockCache, BlockCacheStats, CompressionType, TableReader, TableReaderOptions};
+pub use statistics::{Histogram, HistogramSnapshot, Statistics, StatisticsSnapshot, Ticker};
+pub use table_cache::TableCache;
+pub use write_path::{CloseOptions, FlushOptions, WriteOptions, WritePath, WritePathGauges};
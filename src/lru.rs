@@ -0,0 +1,89 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+struct LruEntry<V> {
+    value: V,
+    charge: usize,
+    /// 最後に使った順番（lruのキー）
+    tick: u64,
+}
+
+/// 容量を超えたら最も長く使っていないものから追い出すLRUキャッシュ
+///
+/// 最後に使った順番をBTreeMapで持ち、小さいものから追い出す。
+/// 容量は値ごとのcharge（ブロックならバイト数、TableReaderなら1）の合計で数える。
+/// スレッド間で共有するときは呼び出し側でMutexに入れる
+pub(crate) struct LruCache<K, V> {
+    capacity: usize,
+    usage: usize,
+    next_tick: u64,
+    entries: HashMap<K, LruEntry<V>>,
+    lru: BTreeMap<u64, K>,
+}
+
+impl<K: Copy + Eq + Hash, V> LruCache<K, V> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            usage: 0,
+            next_tick: 0,
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+        }
+    }
+
+    /// キャッシュにあればLRUの先頭に移して返す
+    pub(crate) fn lookup(&mut self, key: K) -> Option<&V> {
+        let tick = self.next_tick;
+        let entry = self.entries.get_mut(&key)?;
+        self.lru.remove(&entry.tick);
+        entry.tick = tick;
+        self.lru.insert(tick, key);
+        self.next_tick += 1;
+        Some(&entry.value)
+    }
+
+    /// 値を入れる（容量を超えた分は古いものから追い出す）
+    ///
+    /// chargeが容量より大きい値はキャッシュしない
+    pub(crate) fn insert(&mut self, key: K, value: V, charge: usize) {
+        if charge > self.capacity {
+            return;
+        }
+        if let Some(old) = self.entries.remove(&key) {
+            self.lru.remove(&old.tick);
+            self.usage -= old.charge;
+        }
+        while self.usage + charge > self.capacity {
+            let Some((_, oldest)) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                self.usage -= evicted.charge;
+            }
+        }
+
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        self.lru.insert(tick, key);
+        self.entries.insert(key, LruEntry { value, charge, tick });
+        self.usage += charge;
+    }
+
+    /// キャッシュから取り除いて返す
+    pub(crate) fn remove(&mut self, key: K) -> Option<V> {
+        let entry = self.entries.remove(&key)?;
+        self.lru.remove(&entry.tick);
+        self.usage -= entry.charge;
+        Some(entry.value)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// キャッシュしている値のchargeの合計
+    pub(crate) fn usage(&self) -> usize {
+        self.usage
+    }
+}
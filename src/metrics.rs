@@ -0,0 +1,111 @@
+//! WritePathのメトリクスをOpenMetricsのテキスト形式で出力する（Prometheusで収集できる）
+
+use std::fmt::Write;
+
+use crate::statistics::{Histogram, HistogramSnapshot, Ticker};
+use crate::write_path::WritePath;
+
+/// OpenMetricsのテキスト形式のContent-Type
+pub const OPEN_METRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// WritePathのgaugeと、Options::statisticsがあればそのカウンタとヒストグラムを出力する
+///
+/// メトリクスの名前は統計の名前の`.`を`_`に置き換えたもの（`lsm.bytes.written`なら
+/// `lsm_bytes_written_total`）。ヒストグラムのバケットは固定なので、収集のたびに変わらない
+pub fn render_open_metrics(write_path: &WritePath) -> String {
+    let mut out = String::new();
+    let gauges = write_path.gauges();
+    let gauges = [
+        ("lsm_memtable_size_bytes", "Size of the mutable memtable.", gauges.memtable_size),
+        (
+            "lsm_immutable_memtables",
+            "Immutable memtables waiting to be flushed.",
+            gauges.immutable_memtables,
+        ),
+        ("lsm_l0_files", "Number of SSTables in L0.", gauges.l0_files),
+        (
+            "lsm_pending_compaction_bytes",
+            "Estimated bytes to be compacted (always 0 without compaction).",
+            gauges.pending_compaction_bytes,
+        ),
+        ("lsm_write_stalled", "1 while a write is blocked by a write stall.", gauges.write_stalled as u64),
+    ];
+    for (name, help, value) in gauges {
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+
+    if let Some(stats) = &write_path.options().statistics {
+        let snapshot = stats.snapshot();
+        for ticker in Ticker::ALL {
+            let name = metric_name(ticker.name());
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{}_total {}", name, snapshot.ticker(ticker));
+        }
+        for histogram in Histogram::ALL {
+            render_histogram(&mut out, &metric_name(histogram.name()), snapshot.histogram(histogram));
+        }
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+fn render_histogram(out: &mut String, name: &str, histogram: &HistogramSnapshot) {
+    let _ = writeln!(out, "# TYPE {} histogram", name);
+    for (limit, cumulative) in histogram.cumulative_buckets() {
+        // 最後のバケット（上限u64::MAX）は+Infとして出力する
+        if limit == u64::MAX {
+            break;
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, limit, cumulative);
+    }
+    let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, histogram.count);
+    let _ = writeln!(out, "{}_count {}", name, histogram.count);
+    let _ = writeln!(out, "{}_sum {}", name, histogram.sum);
+}
+
+fn metric_name(name: &str) -> String {
+    name.replace('.', "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Options, Statistics};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_render_open_metrics() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let options = Options::builder()
+            .write_buffer_size(1024 * 1024)
+            .statistics(Arc::new(Statistics::new()))
+            .build()
+            .unwrap();
+        let write_path = WritePath::open(temp_dir.path(), options).unwrap();
+        write_path.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        write_path.flush_and_wait().unwrap();
+        write_path.put(b"bb".to_vec(), b"22".to_vec()).unwrap();
+
+        let text = render_open_metrics(&write_path);
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines.contains(&"lsm_memtable_size_bytes 4"), "{}", text);
+        assert!(lines.contains(&"lsm_immutable_memtables 0"), "{}", text);
+        assert!(lines.contains(&"lsm_l0_files 1"), "{}", text);
+        assert!(lines.contains(&"lsm_write_stalled 0"), "{}", text);
+        assert!(lines.contains(&"# TYPE lsm_bytes_written counter"), "{}", text);
+        assert!(lines.contains(&"lsm_bytes_written_total 6"), "{}", text);
+        assert!(lines.contains(&"lsm_put_micros_bucket{le=\"+Inf\"} 2"), "{}", text);
+        assert!(lines.contains(&"lsm_sst_size_count 1"), "{}", text);
+        assert_eq!(lines.last(), Some(&"# EOF"));
+
+        // バケットは累計なので減らない
+        let buckets: Vec<u64> = lines
+            .iter()
+            .filter(|line| line.starts_with("lsm_put_micros_bucket"))
+            .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+            .collect();
+        assert!(buckets.windows(2).all(|w| w[0] <= w[1]));
+    }
+}
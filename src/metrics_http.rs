@@ -0,0 +1,148 @@
+//! メトリクスを返すだけの小さなHTTPサーバ（`metrics_http` feature）
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::metrics::OPEN_METRICS_CONTENT_TYPE;
+
+/// リクエストの読み込みと応答の書き込みのタイムアウト
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 接続がないときに停止の要求を確かめる間隔
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// 読み込むリクエストヘッダの上限
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+/// `GET /metrics`にOpenMetricsのテキストを返すHTTPサーバ
+///
+/// 1つのスレッドでリクエストを順に処理する。Prometheusのscrape用なので、
+/// ローカルのアドレスで動かすことを想定している。破棄すると停止する。
+/// WritePathのメトリクスを返すには、`Arc<WritePath>`を持つクロージャで
+/// `render_open_metrics`を呼ぶ
+pub struct MetricsServer {
+    local_addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// addrで待ち受けを始める。リクエストのたびにrenderを呼んで本文にする
+    pub fn start<A, F>(addr: A, render: F) -> io::Result<Self>
+    where
+        A: ToSocketAddrs,
+        F: Fn() -> String + Send + 'static,
+    {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        // accept()で待ち続けないようにして、停止の要求を必ず確かめる
+        listener.set_nonblocking(true)?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let shutdown = Arc::clone(&shutdown);
+            thread::spawn(move || {
+                while !shutdown.load(Ordering::Acquire) {
+                    match listener.accept() {
+                        // 1つの接続の失敗でサーバを止めない
+                        Ok((stream, _)) => {
+                            if stream.set_nonblocking(false).is_ok() {
+                                let _ = handle_connection(stream, &render);
+                            }
+                        }
+                        // 接続がない（またはfdが足りないなどの一時的なエラー）
+                        Err(_) => thread::sleep(ACCEPT_POLL_INTERVAL),
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            local_addr,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    /// 待ち受けているアドレス（ポート0を指定した場合に実際のポートを知るため）
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        // スレッドはACCEPT_POLL_INTERVAL以内（処理中の接続があればその後）に終了する
+        self.shutdown.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, render: &dyn Fn() -> String) -> io::Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+    // ヘッダの終わりまで読む（本文のあるリクエストは受け付けない）
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf)?;
+        if n == 0 || request.len() + n > MAX_REQUEST_BYTES {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let request_line = request.split(|&b| b == b'\r').next().unwrap_or_default();
+    let mut parts = request_line.split(|&b| b == b' ');
+    let (method, path) = (parts.next(), parts.next());
+    let (status, content_type, body) = match (method, path) {
+        (Some(b"GET"), Some(b"/metrics")) => ("200 OK", OPEN_METRICS_CONTENT_TYPE, render()),
+        (Some(b"GET"), _) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "method not allowed\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_serves_metrics() {
+        let server = MetricsServer::start("127.0.0.1:0", || "lsm_l0_files 3\n# EOF\n".to_string()).unwrap();
+
+        let response = get(server.local_addr(), "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains(OPEN_METRICS_CONTENT_TYPE), "{}", response);
+        assert!(response.ends_with("\r\n\r\nlsm_l0_files 3\n# EOF\n"), "{}", response);
+
+        let response = get(server.local_addr(), "/");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+
+        // 破棄すると待ち受けをやめる
+        let addr = server.local_addr();
+        drop(server);
+        assert!(TcpStream::connect(addr).is_err());
+    }
+}
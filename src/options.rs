@@ -0,0 +1,753 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::error::{Error, Result};
+use crate::event_listener::EventListener;
+use crate::file_system::{read_file, write_file, FileSystem, PosixFileSystem};
+use crate::rate_limiter::RateLimiter;
+use crate::statistics::Statistics;
+use crate::sstable::CompressionType;
+
+/// OPTIONSファイルのファイル名
+pub const OPTIONS_FILE_NAME: &str = "OPTIONS";
+
+/// SSTable作成時の永続化ポリシー
+///
+/// デフォルトはすべて無効（スループット優先でfsyncしない）
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncPolicy {
+    /// SSTableの書き込み完了時にfdatasyncする
+    pub sync_sstables: bool,
+    /// 書き込み中にこのバイト数ごとにrange syncする（0なら無効）
+    pub bytes_per_sync: u64,
+    /// SSTableのrename後にデータディレクトリをfsyncする
+    pub sync_dir: bool,
+}
+
+/// コンパクションの方式（RocksDBのCompactionStyle相当）
+///
+/// コンパクションはまだないので、OPTIONSファイルに保存されるだけで動作は変わらない
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompactionStyle {
+    /// レベルごとのサイズの上限を超えたら下のレベルへマージする
+    #[default]
+    Level,
+    /// 同じくらいのサイズのSSTableをまとめてマージする
+    Universal,
+    /// 合計サイズの上限を超えたら古いSSTableから削除する
+    Fifo,
+}
+
+impl fmt::Display for CompactionStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CompactionStyle::Level => "level",
+            CompactionStyle::Universal => "universal",
+            CompactionStyle::Fifo => "fifo",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for CompactionStyle {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "level" => Ok(CompactionStyle::Level),
+            "universal" => Ok(CompactionStyle::Universal),
+            "fifo" => Ok(CompactionStyle::Fifo),
+            _ => Err(Error::InvalidArgument(format!("unknown compaction style: {:?}", s))),
+        }
+    }
+}
+
+/// WritePathの設定
+///
+/// `Options::builder()`で組み立てるか、`Options::default()`を書き換えて使う。
+/// WritePathを開くとdata_dirにOPTIONSファイルとして保存され、
+/// `WritePath::reopen`で同じ設定を読み込める
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    /// MemTableをimmutable化するサイズ（RocksDBのwrite_buffer_size相当）
+    pub write_buffer_size: usize,
+    /// MemTableの最大数（1 mutable + (max-1) immutable）
+    ///
+    /// 1の場合もimmutable MemTableを1つまでフラッシュ待ちにできる
+    pub max_write_buffer_number: usize,
+    /// SSTable作成時の永続化ポリシー
+    pub sync_policy: SyncPolicy,
+    /// フラッシュスレッドがパニックで終了していた場合、resume()で再起動する
+    pub restart_flush_worker: bool,
+    /// コンパクションの方式（コンパクションはまだないので使われない）
+    pub compaction_style: CompactionStyle,
+    /// L0のSSTableがこの数に達したらコンパクションを始める（コンパクションはまだないので使われない）
+    pub level0_file_num_compaction_trigger: usize,
+    /// SSTableのデータブロックの目安サイズ（非圧縮）
+    pub block_size: usize,
+    /// データブロックでキーを省略せずに書く間隔（エントリ数）
+    ///
+    /// 間のキーは直前のキーとの差分だけを書く。小さいほど読み込み時の線形探索が短くなり、
+    /// 大きいほどブロックが小さくなる
+    pub block_restart_interval: usize,
+    /// データブロックの圧縮方式（compression_per_levelが空のとき）
+    pub compression: CompressionType,
+    /// レベルごとの圧縮方式（レベル数より短ければ最後の要素を使う）
+    ///
+    /// 例: `[None, None, Zstd]` ならL0/L1は非圧縮、L2以降はZstd
+    pub compression_per_level: Vec<CompressionType>,
+    /// 最下層のレベルに使う圧縮方式（Noneならcompression_per_levelに従う）
+    pub bottommost_compression: Option<CompressionType>,
+    /// 圧縮率（非圧縮サイズ/圧縮後サイズ）がこれ未満のブロックは圧縮せずに書く
+    pub min_compression_ratio: f64,
+    /// Zstdの辞書の最大サイズ（0なら辞書を使わない）
+    ///
+    /// SSTableごとにデータブロックをサンプルして辞書を学習し、
+    /// そのファイルのすべてのデータブロックの圧縮に使う。小さな値が多いときに効く
+    pub zstd_max_dict_bytes: usize,
+    /// 辞書の学習に使うデータブロックの合計サイズ
+    ///
+    /// 書き出すデータブロックはこのサイズに達するまでメモリに溜めてから圧縮する
+    pub zstd_max_train_bytes: usize,
+    /// SSTableの読み込みで共有するブロックキャッシュの容量（0ならキャッシュしない）
+    pub block_cache_size: usize,
+    /// index blockもブロックキャッシュに入れる（falseならTableReaderが開いている間保持する）
+    pub cache_index_blocks: bool,
+    /// cache_index_blocksのとき、L0のSSTableのindex blockはTableReaderが保持して追い出さない
+    ///
+    /// filter blockはまだないのでindex blockだけが対象
+    pub pin_l0_index_blocks: bool,
+    /// 同時に開いておくSSTableの最大数（テーブルキャッシュの容量）
+    pub max_open_files: usize,
+    /// SSTableをmmapして読む（ローカルSSDで読み込みが多い場合向け）
+    pub allow_mmap_reads: bool,
+    /// SSTableをページキャッシュを通さずに読む（O_DIRECT、macOSではF_NOCACHE）
+    pub use_direct_reads: bool,
+    /// フラッシュ（とコンパクション）の出力をページキャッシュを通さずに書く
+    ///
+    /// 大量の書き込みで読み込みに使っているページキャッシュを追い出さないようにする。
+    /// ファイルシステムが対応していなければ通常の書き込みになる
+    pub use_direct_io_for_flush_and_compaction: bool,
+    /// SSTableの書き出しをio_uringで行う（Linuxで`io_uring` featureを有効にしたときのみ）
+    ///
+    /// io_uringを使えない環境では通常の書き込みになる。Direct I/Oが優先される
+    pub use_io_uring: bool,
+    /// LOGファイルがこのサイズを超えたらローテーションする（0なら開いたときだけ）
+    pub max_log_file_size: u64,
+    /// 残しておく古いLOGファイル（`LOG.old.*`）の数
+    pub keep_log_file_num: usize,
+    /// LOGに統計を書き出す間隔（秒、0なら書き出さない）
+    ///
+    /// 0でなければWritePathごとに統計を書き出すスレッドを起動する。
+    /// デフォルトは0（RocksDBの600秒とは違い、開くたびにスレッドを増やさない）
+    pub stats_dump_period_sec: u64,
+    /// フラッシュ（とコンパクション）の書き込み速度の制限（Noneなら制限しない）
+    ///
+    /// 複数のWritePathで共有できる。OPTIONSファイルには保存されないので、
+    /// `reopen`では制限されない（`load_from_dir`で読んだ設定に指定し直して`open`する）
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// 書き込みとフラッシュの統計の記録先（Noneなら記録しない）
+    ///
+    /// rate_limiterと同じくOPTIONSファイルには保存されない
+    pub statistics: Option<Arc<Statistics>>,
+    /// フラッシュやwrite stallなどのイベントを受け取るリスナー（登録した順に呼ばれる）
+    ///
+    /// OPTIONSファイルには保存されない
+    pub listeners: Vec<Arc<dyn EventListener>>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            write_buffer_size: 64 * 1024 * 1024,
+            max_write_buffer_number: 2,
+            sync_policy: SyncPolicy::default(),
+            restart_flush_worker: false,
+            compaction_style: CompactionStyle::Level,
+            // RocksDBと同じ
+            level0_file_num_compaction_trigger: 4,
+            block_size: 4 * 1024,
+            block_restart_interval: 16,
+            compression: CompressionType::None,
+            compression_per_level: Vec::new(),
+            bottommost_compression: None,
+            // RocksDBと同じく12.5%以上小さくならなければ圧縮しない
+            min_compression_ratio: 1.125,
+            zstd_max_dict_bytes: 0,
+            zstd_max_train_bytes: 1024 * 1024,
+            block_cache_size: 8 * 1024 * 1024,
+            cache_index_blocks: false,
+            pin_l0_index_blocks: false,
+            max_open_files: 1000,
+            allow_mmap_reads: false,
+            use_direct_reads: false,
+            use_direct_io_for_flush_and_compaction: false,
+            use_io_uring: false,
+            max_log_file_size: 16 * 1024 * 1024,
+            // RocksDBと同じ
+            keep_log_file_num: 1000,
+            stats_dump_period_sec: 0,
+            rate_limiter: None,
+            statistics: None,
+            listeners: Vec::new(),
+        }
+    }
+}
+
+impl Options {
+    /// デフォルト値から始めるビルダーを作成
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder {
+            options: Options::default(),
+        }
+    }
+
+    /// 設定値の組み合わせが正しいか検証する
+    pub fn validate(&self) -> Result<()> {
+        if self.write_buffer_size == 0 {
+            return Err(Error::InvalidArgument(
+                "write_buffer_size must be greater than 0".to_string(),
+            ));
+        }
+        // 1でもチャネルの容量は1になる（immutable MemTable 1つまではwrite stallしない）
+        if self.max_write_buffer_number == 0 {
+            return Err(Error::InvalidArgument(
+                "max_write_buffer_number must be greater than 0".to_string(),
+            ));
+        }
+        if self.level0_file_num_compaction_trigger == 0 {
+            return Err(Error::InvalidArgument(
+                "level0_file_num_compaction_trigger must be greater than 0".to_string(),
+            ));
+        }
+        if self.block_size == 0 {
+            return Err(Error::InvalidArgument("block_size must be greater than 0".to_string()));
+        }
+        if self.block_restart_interval == 0 {
+            return Err(Error::InvalidArgument(
+                "block_restart_interval must be greater than 0".to_string(),
+            ));
+        }
+        if !(self.min_compression_ratio >= 1.0 && self.min_compression_ratio.is_finite()) {
+            return Err(Error::InvalidArgument(format!(
+                "min_compression_ratio must be a finite value >= 1.0, got {}",
+                self.min_compression_ratio
+            )));
+        }
+        if self.max_open_files == 0 {
+            return Err(Error::InvalidArgument("max_open_files must be greater than 0".to_string()));
+        }
+        if self.allow_mmap_reads && self.use_direct_reads {
+            return Err(Error::InvalidArgument(
+                "allow_mmap_reads and use_direct_reads cannot be enabled together".to_string(),
+            ));
+        }
+        if self.use_io_uring && !cfg!(all(feature = "io_uring", target_os = "linux")) {
+            return Err(Error::InvalidArgument(
+                "use_io_uring requires Linux and the io_uring feature".to_string(),
+            ));
+        }
+        if self.zstd_max_dict_bytes > 0 && self.zstd_max_train_bytes < self.zstd_max_dict_bytes {
+            return Err(Error::InvalidArgument(format!(
+                "zstd_max_train_bytes ({}) must be at least zstd_max_dict_bytes ({})",
+                self.zstd_max_train_bytes, self.zstd_max_dict_bytes
+            )));
+        }
+        Ok(())
+    }
+
+    /// 指定したレベルに書き出すSSTableの圧縮方式
+    ///
+    /// フラッシュはL0に書き出す（コンパクションはまだないので最下層として扱わない）
+    pub fn compression_for_level(&self, level: usize, is_bottommost: bool) -> CompressionType {
+        if is_bottommost {
+            if let Some(compression) = self.bottommost_compression {
+                return compression;
+            }
+        }
+        match self.compression_per_level.last() {
+            Some(last) => *self.compression_per_level.get(level).unwrap_or(last),
+            None => self.compression,
+        }
+    }
+
+    /// data_dirにOPTIONSファイルとして保存する
+    ///
+    /// 一時ファイルに書き出してからrenameするので、途中の状態は見えない
+    pub fn save_to_dir<P: AsRef<Path>>(&self, data_dir: P) -> Result<()> {
+        self.save_to_dir_with(&PosixFileSystem, data_dir.as_ref())
+    }
+
+    /// ファイルシステムを指定してOPTIONSファイルを保存する
+    pub(crate) fn save_to_dir_with(&self, fs: &dyn FileSystem, data_dir: &Path) -> Result<()> {
+        let tmp_path = data_dir.join(format!("{}.tmp", OPTIONS_FILE_NAME));
+        write_file(fs, &tmp_path, self.to_options_string().as_bytes())?;
+        fs.rename(&tmp_path, &data_dir.join(OPTIONS_FILE_NAME))?;
+        // renameを永続化しないと、電源断の後にOPTIONSファイルがなくなることがある
+        fs.sync_dir(data_dir)?;
+        Ok(())
+    }
+
+    /// data_dirのOPTIONSファイルを読み込む
+    ///
+    /// ファイルがなければ`Error::NotFound`、内容が不正なら`Error::Corruption`を返す
+    pub fn load_from_dir<P: AsRef<Path>>(data_dir: P) -> Result<Self> {
+        Self::load_from_dir_with(&PosixFileSystem, data_dir.as_ref())
+    }
+
+    /// ファイルシステムを指定してOPTIONSファイルを読み込む
+    pub(crate) fn load_from_dir_with(fs: &dyn FileSystem, data_dir: &Path) -> Result<Self> {
+        let path = data_dir.join(OPTIONS_FILE_NAME);
+        let content = read_file(fs, &path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::NotFound(format!("{}", path.display())),
+            _ => Error::Io(e),
+        })?;
+        let content = String::from_utf8(content)
+            .map_err(|_| Error::Corruption("OPTIONS file is not valid UTF-8".to_string()))?;
+        let options = Self::parse_options_string(&content)?;
+        options.validate()?;
+        Ok(options)
+    }
+
+    /// OPTIONSファイルの内容（`key=value`形式）
+    pub(crate) fn to_options_string(&self) -> String {
+        format!(
+            "# learning-lsm-write-path OPTIONS\n\
+             write_buffer_size={}\n\
+             max_write_buffer_number={}\n\
+             sync_sstables={}\n\
+             bytes_per_sync={}\n\
+             sync_dir={}\n\
+             restart_flush_worker={}\n\
+             compaction_style={}\n\
+             level0_file_num_compaction_trigger={}\n\
+             block_size={}\n\
+             block_restart_interval={}\n\
+             compression={}\n\
+             compression_per_level={}\n\
+             bottommost_compression={}\n\
+             min_compression_ratio={}\n\
+             zstd_max_dict_bytes={}\n\
+             zstd_max_train_bytes={}\n\
+             block_cache_size={}\n\
+             cache_index_blocks={}\n\
+             pin_l0_index_blocks={}\n\
+             max_open_files={}\n\
+             allow_mmap_reads={}\n\
+             use_direct_reads={}\n\
+             use_direct_io_for_flush_and_compaction={}\n\
+             use_io_uring={}\n\
+             max_log_file_size={}\n\
+             keep_log_file_num={}\n\
+             stats_dump_period_sec={}\n",
+            self.write_buffer_size,
+            self.max_write_buffer_number,
+            self.sync_policy.sync_sstables,
+            self.sync_policy.bytes_per_sync,
+            self.sync_policy.sync_dir,
+            self.restart_flush_worker,
+            self.compaction_style,
+            self.level0_file_num_compaction_trigger,
+            self.block_size,
+            self.block_restart_interval,
+            self.compression,
+            self.compression_per_level
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .join(":"),
+            self.bottommost_compression.map_or(String::new(), |c| c.to_string()),
+            self.min_compression_ratio,
+            self.zstd_max_dict_bytes,
+            self.zstd_max_train_bytes,
+            self.block_cache_size,
+            self.cache_index_blocks,
+            self.pin_l0_index_blocks,
+            self.max_open_files,
+            self.allow_mmap_reads,
+            self.use_direct_reads,
+            self.use_direct_io_for_flush_and_compaction,
+            self.use_io_uring,
+            self.max_log_file_size,
+            self.keep_log_file_num,
+            self.stats_dump_period_sec,
+        )
+    }
+
+    fn parse_options_string(content: &str) -> Result<Self> {
+        let mut options = Options::default();
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                Error::Corruption(format!("OPTIONS line {}: expected key=value, got {:?}", i + 1, line))
+            })?;
+            let (key, value) = (key.trim(), value.trim());
+
+            match key {
+                "write_buffer_size" => options.write_buffer_size = parse_value(key, value)?,
+                "max_write_buffer_number" => {
+                    options.max_write_buffer_number = parse_value(key, value)?
+                }
+                "sync_sstables" => options.sync_policy.sync_sstables = parse_value(key, value)?,
+                "bytes_per_sync" => options.sync_policy.bytes_per_sync = parse_value(key, value)?,
+                "sync_dir" => options.sync_policy.sync_dir = parse_value(key, value)?,
+                "restart_flush_worker" => options.restart_flush_worker = parse_value(key, value)?,
+                "compaction_style" => options.compaction_style = parse_value(key, value)?,
+                "level0_file_num_compaction_trigger" => {
+                    options.level0_file_num_compaction_trigger = parse_value(key, value)?
+                }
+                "block_size" => options.block_size = parse_value(key, value)?,
+                "block_restart_interval" => options.block_restart_interval = parse_value(key, value)?,
+                "compression" => options.compression = parse_value(key, value)?,
+                "compression_per_level" => {
+                    options.compression_per_level = value
+                        .split(':')
+                        .filter(|c| !c.is_empty())
+                        .map(|c| parse_value(key, c))
+                        .collect::<Result<_>>()?
+                }
+                "bottommost_compression" => {
+                    options.bottommost_compression = match value {
+                        "" => None,
+                        _ => Some(parse_value(key, value)?),
+                    }
+                }
+                "min_compression_ratio" => options.min_compression_ratio = parse_value(key, value)?,
+                "zstd_max_dict_bytes" => options.zstd_max_dict_bytes = parse_value(key, value)?,
+                "zstd_max_train_bytes" => options.zstd_max_train_bytes = parse_value(key, value)?,
+                "block_cache_size" => options.block_cache_size = parse_value(key, value)?,
+                "cache_index_blocks" => options.cache_index_blocks = parse_value(key, value)?,
+                "pin_l0_index_blocks" => options.pin_l0_index_blocks = parse_value(key, value)?,
+                "max_open_files" => options.max_open_files = parse_value(key, value)?,
+                "allow_mmap_reads" => options.allow_mmap_reads = parse_value(key, value)?,
+                "use_direct_reads" => options.use_direct_reads = parse_value(key, value)?,
+                "use_direct_io_for_flush_and_compaction" => {
+                    options.use_direct_io_for_flush_and_compaction = parse_value(key, value)?
+                }
+                "use_io_uring" => options.use_io_uring = parse_value(key, value)?,
+                "max_log_file_size" => options.max_log_file_size = parse_value(key, value)?,
+                "keep_log_file_num" => options.keep_log_file_num = parse_value(key, value)?,
+                "stats_dump_period_sec" => options.stats_dump_period_sec = parse_value(key, value)?,
+                _ => {
+                    return Err(Error::Corruption(format!(
+                        "OPTIONS line {}: unknown option {:?}",
+                        i + 1,
+                        key
+                    )))
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Optionsのビルダー
+#[derive(Clone, Debug)]
+pub struct OptionsBuilder {
+    options: Options,
+}
+
+impl OptionsBuilder {
+    /// MemTableをimmutable化するサイズ
+    pub fn write_buffer_size(mut self, size: usize) -> Self {
+        self.options.write_buffer_size = size;
+        self
+    }
+
+    /// MemTableの最大数（1 mutable + (max-1) immutable）
+    pub fn max_write_buffer_number(mut self, n: usize) -> Self {
+        self.options.max_write_buffer_number = n;
+        self
+    }
+
+    /// SSTable作成時の永続化ポリシー
+    pub fn sync_policy(mut self, sync_policy: SyncPolicy) -> Self {
+        self.options.sync_policy = sync_policy;
+        self
+    }
+
+    /// フラッシュスレッドがパニックで終了していた場合、resume()で再起動する
+    pub fn restart_flush_worker(mut self, restart: bool) -> Self {
+        self.options.restart_flush_worker = restart;
+        self
+    }
+
+    /// コンパクションの方式
+    pub fn compaction_style(mut self, style: CompactionStyle) -> Self {
+        self.options.compaction_style = style;
+        self
+    }
+
+    /// L0のコンパクションを始めるSSTableの数
+    pub fn level0_file_num_compaction_trigger(mut self, n: usize) -> Self {
+        self.options.level0_file_num_compaction_trigger = n;
+        self
+    }
+
+    /// SSTableのデータブロックの目安サイズ
+    pub fn block_size(mut self, size: usize) -> Self {
+        self.options.block_size = size;
+        self
+    }
+
+    /// データブロックでキーを省略せずに書く間隔（エントリ数）
+    pub fn block_restart_interval(mut self, interval: usize) -> Self {
+        self.options.block_restart_interval = interval;
+        self
+    }
+
+    /// データブロックの圧縮方式
+    pub fn compression(mut self, compression: CompressionType) -> Self {
+        self.options.compression = compression;
+        self
+    }
+
+    /// レベルごとの圧縮方式
+    pub fn compression_per_level(mut self, compression_per_level: Vec<CompressionType>) -> Self {
+        self.options.compression_per_level = compression_per_level;
+        self
+    }
+
+    /// 最下層のレベルに使う圧縮方式
+    pub fn bottommost_compression(mut self, compression: CompressionType) -> Self {
+        self.options.bottommost_compression = Some(compression);
+        self
+    }
+
+    /// 圧縮したブロックを採用する最小の圧縮率
+    pub fn min_compression_ratio(mut self, ratio: f64) -> Self {
+        self.options.min_compression_ratio = ratio;
+        self
+    }
+
+    /// Zstdの辞書の最大サイズ（0なら辞書を使わない）
+    pub fn zstd_max_dict_bytes(mut self, size: usize) -> Self {
+        self.options.zstd_max_dict_bytes = size;
+        self
+    }
+
+    /// 辞書の学習に使うデータブロックの合計サイズ
+    pub fn zstd_max_train_bytes(mut self, size: usize) -> Self {
+        self.options.zstd_max_train_bytes = size;
+        self
+    }
+
+    /// ブロックキャッシュの容量（0ならキャッシュしない）
+    pub fn block_cache_size(mut self, size: usize) -> Self {
+        self.options.block_cache_size = size;
+        self
+    }
+
+    /// index blockもブロックキャッシュに入れる
+    pub fn cache_index_blocks(mut self, cache: bool) -> Self {
+        self.options.cache_index_blocks = cache;
+        self
+    }
+
+    /// L0のSSTableのindex blockをTableReaderが保持する
+    pub fn pin_l0_index_blocks(mut self, pin: bool) -> Self {
+        self.options.pin_l0_index_blocks = pin;
+        self
+    }
+
+    /// 同時に開いておくSSTableの最大数
+    pub fn max_open_files(mut self, n: usize) -> Self {
+        self.options.max_open_files = n;
+        self
+    }
+
+    /// SSTableをmmapして読む
+    pub fn allow_mmap_reads(mut self, allow: bool) -> Self {
+        self.options.allow_mmap_reads = allow;
+        self
+    }
+
+    /// SSTableをページキャッシュを通さずに読む
+    pub fn use_direct_reads(mut self, direct: bool) -> Self {
+        self.options.use_direct_reads = direct;
+        self
+    }
+
+    /// フラッシュの出力をページキャッシュを通さずに書く
+    pub fn use_direct_io_for_flush_and_compaction(mut self, direct: bool) -> Self {
+        self.options.use_direct_io_for_flush_and_compaction = direct;
+        self
+    }
+
+    /// SSTableの書き出しをio_uringで行う
+    pub fn use_io_uring(mut self, use_io_uring: bool) -> Self {
+        self.options.use_io_uring = use_io_uring;
+        self
+    }
+
+    /// LOGファイルをローテーションするサイズ（0なら開いたときだけ）
+    pub fn max_log_file_size(mut self, size: u64) -> Self {
+        self.options.max_log_file_size = size;
+        self
+    }
+
+    /// 残しておく古いLOGファイルの数
+    pub fn keep_log_file_num(mut self, num: usize) -> Self {
+        self.options.keep_log_file_num = num;
+        self
+    }
+
+    /// LOGに統計を書き出す間隔（秒、0なら書き出さない）
+    pub fn stats_dump_period_sec(mut self, secs: u64) -> Self {
+        self.options.stats_dump_period_sec = secs;
+        self
+    }
+
+    /// フラッシュの書き込み速度を制限する（複数のWritePathで共有できる）
+    pub fn rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.options.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// 統計を記録する（複数のWritePathで共有できる）
+    pub fn statistics(mut self, statistics: Arc<Statistics>) -> Self {
+        self.options.statistics = Some(statistics);
+        self
+    }
+
+    /// イベントリスナーを追加する
+    pub fn add_listener(mut self, listener: Arc<dyn EventListener>) -> Self {
+        self.options.listeners.push(listener);
+        self
+    }
+
+    /// 設定を検証してOptionsを作成
+    pub fn build(self) -> Result<Options> {
+        self.options.validate()?;
+        Ok(self.options)
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(key: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| Error::Corruption(format!("OPTIONS: invalid value {:?} for {}", value, key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_builder_validation() {
+        let options = Options::builder()
+            .write_buffer_size(1024)
+            .max_write_buffer_number(4)
+            .build()
+            .unwrap();
+        assert_eq!(options.write_buffer_size, 1024);
+        assert_eq!(options.max_write_buffer_number, 4);
+
+        let err = Options::builder().write_buffer_size(0).build().unwrap_err();
+        assert!(matches!(err, Error::InvalidArgument(_)));
+        assert!(err.to_string().contains("write_buffer_size"));
+
+        // mutable 1つだけでもよい
+        assert!(Options::builder().max_write_buffer_number(1).build().is_ok());
+        let err = Options::builder().max_write_buffer_number(0).build().unwrap_err();
+        assert!(err.to_string().contains("max_write_buffer_number"));
+
+        let err = Options::builder().level0_file_num_compaction_trigger(0).build().unwrap_err();
+        assert!(err.to_string().contains("level0_file_num_compaction_trigger"));
+
+        let err = Options::builder().block_restart_interval(0).build().unwrap_err();
+        assert!(err.to_string().contains("block_restart_interval"));
+
+        let err = Options::builder()
+            .allow_mmap_reads(true)
+            .use_direct_reads(true)
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().contains("use_direct_reads"));
+
+        let err = Options::builder().min_compression_ratio(0.5).build().unwrap_err();
+        assert!(err.to_string().contains("min_compression_ratio"));
+
+        let err = Options::builder()
+            .zstd_max_dict_bytes(16 * 1024)
+            .zstd_max_train_bytes(1024)
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().contains("zstd_max_train_bytes"));
+    }
+
+    #[test]
+    fn test_compression_for_level() {
+        let options = Options::builder()
+            .compression(CompressionType::Snappy)
+            .build()
+            .unwrap();
+        assert_eq!(options.compression_for_level(0, false), CompressionType::Snappy);
+        assert_eq!(options.compression_for_level(6, true), CompressionType::Snappy);
+
+        let options = Options::builder()
+            .compression_per_level(vec![CompressionType::None, CompressionType::Lz4])
+            .bottommost_compression(CompressionType::Zstd)
+            .build()
+            .unwrap();
+        assert_eq!(options.compression_for_level(0, false), CompressionType::None);
+        assert_eq!(options.compression_for_level(1, false), CompressionType::Lz4);
+        assert_eq!(options.compression_for_level(5, false), CompressionType::Lz4);
+        assert_eq!(options.compression_for_level(5, true), CompressionType::Zstd);
+    }
+
+    #[test]
+    fn test_save_and_load_options_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let options = Options::builder()
+            .write_buffer_size(4096)
+            .max_write_buffer_number(3)
+            .sync_policy(SyncPolicy {
+                sync_sstables: true,
+                bytes_per_sync: 1 << 20,
+                sync_dir: true,
+            })
+            .restart_flush_worker(true)
+            .compaction_style(CompactionStyle::Universal)
+            .level0_file_num_compaction_trigger(8)
+            .block_size(16 * 1024)
+            .block_restart_interval(8)
+            .compression_per_level(vec![CompressionType::None, CompressionType::Lz4])
+            .bottommost_compression(CompressionType::Zstd)
+            .min_compression_ratio(1.5)
+            .zstd_max_dict_bytes(16 * 1024)
+            .zstd_max_train_bytes(256 * 1024)
+            .block_cache_size(0)
+            .cache_index_blocks(true)
+            .pin_l0_index_blocks(true)
+            .max_open_files(16)
+            .allow_mmap_reads(true)
+            .use_direct_io_for_flush_and_compaction(true)
+            .build()
+            .unwrap();
+
+        options.save_to_dir(temp_dir.path()).unwrap();
+        assert_eq!(Options::load_from_dir(temp_dir.path()).unwrap(), options);
+
+        // 不正な内容は読み込めない
+        fs::write(temp_dir.path().join(OPTIONS_FILE_NAME), "write_buffer_size=abc\n").unwrap();
+        let err = Options::load_from_dir(temp_dir.path()).unwrap_err();
+        assert!(matches!(err, Error::Corruption(_)));
+
+        fs::write(temp_dir.path().join(OPTIONS_FILE_NAME), "no_such_option=1\n").unwrap();
+        assert!(Options::load_from_dir(temp_dir.path()).is_err());
+
+        fs::remove_file(temp_dir.path().join(OPTIONS_FILE_NAME)).unwrap();
+        let err = Options::load_from_dir(temp_dir.path()).unwrap_err();
+        assert!(matches!(err, Error::NotFound(_)));
+    }
+}
@@ -0,0 +1,161 @@
+//! 1回の呼び出しの中で時間がどこにかかったかを記録するスレッドローカルのカウンタ
+//! （RocksDBのPerfContext相当）
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::time::Instant;
+
+/// PerfContextに記録する内容
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PerfLevel {
+    /// 何も記録しない（デフォルト）
+    #[default]
+    Disable,
+    /// 回数とバイト数だけを記録する
+    EnableCount,
+    /// 時間も記録する（計測のたびに時刻を取得するので遅くなる）
+    EnableTime,
+}
+
+/// 現在のスレッドの処理の内訳
+///
+/// 値は`reset_perf_context`を呼ぶまで加算され続ける。
+/// WALとbloom filterはまだないので、それらの項目は常に0
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PerfContext {
+    /// putでmemtableのロックを待った時間
+    pub write_mutex_wait_nanos: u64,
+    /// MemTableへの挿入にかかった時間
+    pub write_memtable_nanos: u64,
+    pub write_wal_nanos: u64,
+    pub wal_sync_nanos: u64,
+    /// freeze_memtableでwrite stallにより待たされた時間
+    pub write_stall_nanos: u64,
+    /// ブロックキャッシュを引いた時間
+    pub block_cache_lookup_nanos: u64,
+    pub block_cache_hit_count: u64,
+    pub filter_nanos: u64,
+    /// ファイルから読んだブロックの数とバイト数
+    pub block_read_count: u64,
+    pub block_read_bytes: u64,
+    /// ブロックの読み込み（ディスク）にかかった時間
+    pub block_read_nanos: u64,
+    /// ブロックの展開にかかった時間
+    pub block_decompress_nanos: u64,
+}
+
+thread_local! {
+    static PERF_LEVEL: Cell<PerfLevel> = const { Cell::new(PerfLevel::Disable) };
+    static PERF_CONTEXT: RefCell<PerfContext> = RefCell::new(PerfContext::default());
+}
+
+/// 現在のスレッドで記録する内容を設定する
+pub fn set_perf_level(level: PerfLevel) {
+    PERF_LEVEL.with(|l| l.set(level));
+}
+
+pub fn perf_level() -> PerfLevel {
+    PERF_LEVEL.with(Cell::get)
+}
+
+/// 現在のスレッドのPerfContextのコピー
+pub fn perf_context() -> PerfContext {
+    PERF_CONTEXT.with(|ctx| ctx.borrow().clone())
+}
+
+/// 現在のスレッドのPerfContextを0に戻す
+pub fn reset_perf_context() {
+    PERF_CONTEXT.with(|ctx| *ctx.borrow_mut() = PerfContext::default());
+}
+
+/// 回数やバイト数を加算する（EnableCount以上のとき）
+pub(crate) fn perf_add(field: fn(&mut PerfContext) -> &mut u64, n: u64) {
+    if perf_level() >= PerfLevel::EnableCount {
+        PERF_CONTEXT.with(|ctx| *field(&mut ctx.borrow_mut()) += n);
+    }
+}
+
+/// 区間の時間を計る（EnableTimeのときだけ時刻を取得する）
+pub(crate) struct PerfTimer {
+    start: Option<Instant>,
+}
+
+impl PerfTimer {
+    pub(crate) fn start() -> Self {
+        Self {
+            start: (perf_level() >= PerfLevel::EnableTime).then(Instant::now),
+        }
+    }
+
+    /// 開始からの時間をfieldに加算する
+    pub(crate) fn stop(self, field: fn(&mut PerfContext) -> &mut u64) {
+        if let Some(start) = self.start {
+            let nanos = start.elapsed().as_nanos() as u64;
+            PERF_CONTEXT.with(|ctx| *field(&mut ctx.borrow_mut()) += nanos);
+        }
+    }
+}
+
+/// 0でない項目を`name = value`の形でカンマ区切りで出力する
+impl fmt::Display for PerfContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields = [
+            ("write_mutex_wait_nanos", self.write_mutex_wait_nanos),
+            ("write_memtable_nanos", self.write_memtable_nanos),
+            ("write_wal_nanos", self.write_wal_nanos),
+            ("wal_sync_nanos", self.wal_sync_nanos),
+            ("write_stall_nanos", self.write_stall_nanos),
+            ("block_cache_lookup_nanos", self.block_cache_lookup_nanos),
+            ("block_cache_hit_count", self.block_cache_hit_count),
+            ("filter_nanos", self.filter_nanos),
+            ("block_read_count", self.block_read_count),
+            ("block_read_bytes", self.block_read_bytes),
+            ("block_read_nanos", self.block_read_nanos),
+            ("block_decompress_nanos", self.block_decompress_nanos),
+        ];
+        let mut first = true;
+        for (name, value) in fields.into_iter().filter(|&(_, value)| value > 0) {
+            if !first {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} = {}", name, value)?;
+            first = false;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_perf_level_and_reset() {
+        // 他のテストと混ざらないよう、専用のスレッドで確かめる
+        std::thread::spawn(|| {
+            perf_add(|ctx| &mut ctx.block_read_count, 1);
+            assert_eq!(perf_context(), PerfContext::default());
+
+            set_perf_level(PerfLevel::EnableCount);
+            perf_add(|ctx| &mut ctx.block_read_count, 2);
+            let timer = PerfTimer::start();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            timer.stop(|ctx| &mut ctx.block_read_nanos);
+            assert_eq!(perf_context().block_read_count, 2);
+            assert_eq!(perf_context().block_read_nanos, 0);
+
+            set_perf_level(PerfLevel::EnableTime);
+            let timer = PerfTimer::start();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            timer.stop(|ctx| &mut ctx.block_read_nanos);
+            assert!(perf_context().block_read_nanos >= 1_000_000);
+            assert!(perf_context().to_string().starts_with("block_read_count = 2, block_read_nanos = "));
+
+            reset_perf_context();
+            assert_eq!(perf_context(), PerfContext::default());
+            assert_eq!(perf_context().to_string(), "");
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,420 @@
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::file_system::WritableFile;
+
+/// 補充間隔のデフォルト（RocksDBと同じ100ms）
+pub const DEFAULT_REFILL_PERIOD: Duration = Duration::from_millis(100);
+
+/// 優先度の低い要求を先に処理する割合のデフォルト（10回に1回）
+pub const DEFAULT_FAIRNESS: u32 = 10;
+
+/// 自動調整でこの数の補充ごとに速度を見直す
+const AUTO_TUNE_WINDOW: u32 = 20;
+
+/// 自動調整の下限（上限のこの割合）
+const AUTO_TUNE_MIN_DIVISOR: u64 = 20;
+
+/// I/Oの優先度
+///
+/// フラッシュはHigh、コンパクション（まだない）はLowで要求する
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoPriority {
+    Low,
+    High,
+}
+
+impl IoPriority {
+    fn index(self) -> usize {
+        match self {
+            IoPriority::Low => 0,
+            IoPriority::High => 1,
+        }
+    }
+}
+
+/// バックグラウンドの書き込み速度を制限するトークンバケット（RocksDBのGenericRateLimiter相当）
+///
+/// refill_periodごとに`bytes_per_sec * refill_period`バイト分のトークンを補充し、
+/// 待っている要求に優先度の順に割り当てる。Lowが待たされ続けないよう、
+/// fairness回に1回はLowを先に処理する。
+/// 複数のWritePathで共有すれば、合計の書き込み速度を制限できる
+///
+/// 自動調整を有効にすると、`bytes_per_sec`を上限として、要求が待たされる頻度に合わせて
+/// 上限の1/20から上限までの間で速度を変える。フラッシュ（やコンパクション）が溜まって
+/// 待ちが続くほど速度が上がる
+pub struct RateLimiter {
+    refill_period: Duration,
+    fairness: u32,
+    auto_tuned: bool,
+    state: Mutex<RateLimiterState>,
+    /// トークンの補充と割り当てを通知する
+    granted_cv: Condvar,
+}
+
+struct PendingRequest {
+    id: u64,
+    bytes: u64,
+}
+
+struct RateLimiterState {
+    bytes_per_sec: u64,
+    /// 自動調整の上限（自動調整しなければbytes_per_secと同じ）
+    max_bytes_per_sec: u64,
+    /// 使えるトークン（バイト）
+    available: u64,
+    next_refill: Instant,
+    /// 優先度ごとの待っている要求（古い順）
+    queues: [VecDeque<PendingRequest>; 2],
+    /// トークンを割り当て済みで、要求したスレッドがまだ受け取っていないもの
+    granted: HashSet<u64>,
+    next_id: u64,
+    /// 補充の回数（fairnessの判定に使う）
+    refills: u64,
+    /// 自動調整: 前回の見直しからの補充の回数と、そのうち要求が残った回数
+    tune_periods: u32,
+    tune_drained: u32,
+    total_bytes: [u64; 2],
+    total_requests: [u64; 2],
+}
+
+impl RateLimiter {
+    /// 1秒あたりbytes_per_secバイトに制限する（補充間隔と公平性はデフォルト）
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self::with_options(bytes_per_sec, DEFAULT_REFILL_PERIOD, DEFAULT_FAIRNESS, false)
+    }
+
+    /// 補充間隔、公平性、自動調整を指定して作成
+    ///
+    /// auto_tunedならbytes_per_secは上限になり、上限の半分から始める
+    pub fn with_options(bytes_per_sec: u64, refill_period: Duration, fairness: u32, auto_tuned: bool) -> Self {
+        let max_bytes_per_sec = bytes_per_sec.max(1);
+        let initial = match auto_tuned {
+            true => (max_bytes_per_sec / 2).max(1),
+            false => max_bytes_per_sec,
+        };
+        let refill_period = refill_period.max(Duration::from_millis(1));
+        Self {
+            refill_period,
+            fairness: fairness.max(1),
+            auto_tuned,
+            state: Mutex::new(RateLimiterState {
+                bytes_per_sec: initial,
+                max_bytes_per_sec,
+                available: refill_bytes(initial, refill_period),
+                next_refill: Instant::now() + refill_period,
+                queues: [VecDeque::new(), VecDeque::new()],
+                granted: HashSet::new(),
+                next_id: 0,
+                refills: 0,
+                tune_periods: 0,
+                tune_drained: 0,
+                total_bytes: [0; 2],
+                total_requests: [0; 2],
+            }),
+            granted_cv: Condvar::new(),
+        }
+    }
+
+    /// bytesバイト分のトークンを得るまで待つ
+    ///
+    /// 1回で要求できるのは`single_burst_bytes()`までで、それを超える分は切り詰める
+    pub fn request(&self, bytes: u64, priority: IoPriority) {
+        let mut state = self.lock();
+        let Some(id) = self.enqueue(&mut state, bytes, priority) else {
+            return;
+        };
+        loop {
+            let now = Instant::now();
+            if now >= state.next_refill {
+                self.refill(&mut state, now);
+                self.granted_cv.notify_all();
+            }
+            if state.granted.remove(&id) {
+                return;
+            }
+            let timeout = state.next_refill.saturating_duration_since(now);
+            state = self
+                .granted_cv
+                .wait_timeout(state, timeout)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    /// 1回の要求で得られる最大のバイト数（1回の補充量）
+    pub fn single_burst_bytes(&self) -> u64 {
+        refill_bytes(self.lock().bytes_per_sec, self.refill_period)
+    }
+
+    /// 現在の速度（自動調整では変化する）
+    pub fn bytes_per_second(&self) -> u64 {
+        self.lock().bytes_per_sec
+    }
+
+    /// 速度を変える（自動調整では上限を変える）
+    pub fn set_bytes_per_second(&self, bytes_per_sec: u64) {
+        let mut state = self.lock();
+        state.max_bytes_per_sec = bytes_per_sec.max(1);
+        state.bytes_per_sec = match self.auto_tuned {
+            true => state.bytes_per_sec.clamp(auto_tune_min(state.max_bytes_per_sec), state.max_bytes_per_sec),
+            false => state.max_bytes_per_sec,
+        };
+    }
+
+    /// 優先度ごとの要求したバイト数の合計
+    pub fn total_bytes_through(&self, priority: IoPriority) -> u64 {
+        self.lock().total_bytes[priority.index()]
+    }
+
+    /// 優先度ごとの要求の数
+    pub fn total_requests(&self, priority: IoPriority) -> u64 {
+        self.lock().total_requests[priority.index()]
+    }
+
+    /// 要求を数えてトークンを取る。足りなければキューに積み、割り当てを待つためのidを返す
+    fn enqueue(&self, state: &mut RateLimiterState, bytes: u64, priority: IoPriority) -> Option<u64> {
+        let bytes = bytes.min(refill_bytes(state.bytes_per_sec, self.refill_period));
+        let index = priority.index();
+        state.total_bytes[index] += bytes;
+        state.total_requests[index] += 1;
+
+        // 待っている要求がなくトークンが足りればすぐに返す
+        if state.queues.iter().all(VecDeque::is_empty) && state.available >= bytes {
+            state.available -= bytes;
+            return None;
+        }
+
+        let id = state.next_id;
+        state.next_id += 1;
+        state.queues[index].push_back(PendingRequest { id, bytes });
+        Some(id)
+    }
+
+    /// トークンを補充し、待っている要求に割り当てる
+    fn refill(&self, state: &mut RateLimiterState, now: Instant) {
+        if self.auto_tuned {
+            self.tune(state);
+        }
+        let full = refill_bytes(state.bytes_per_sec, self.refill_period);
+        state.available = full;
+        state.next_refill = now + self.refill_period;
+        state.refills += 1;
+
+        let order = match state.refills.is_multiple_of(self.fairness as u64) {
+            true => [IoPriority::Low, IoPriority::High],
+            false => [IoPriority::High, IoPriority::Low],
+        };
+        'grant: for priority in order {
+            let queue = priority.index();
+            while let Some(request) = state.queues[queue].front() {
+                // 先頭が足りなければ、後ろの小さな要求にも追い越させない
+                // （速度が下がって1回の補充量を超えた要求は、補充分をすべて使って通す）
+                if request.bytes > state.available && state.available < full {
+                    break 'grant;
+                }
+                let request = state.queues[queue].pop_front().unwrap();
+                state.available = state.available.saturating_sub(request.bytes);
+                state.granted.insert(request.id);
+            }
+        }
+    }
+
+    /// 補充を待っている要求がある（前の補充分を使い切った）頻度で速度を上げ下げする
+    fn tune(&self, state: &mut RateLimiterState) {
+        state.tune_periods += 1;
+        if state.queues.iter().any(|queue| !queue.is_empty()) {
+            state.tune_drained += 1;
+        }
+        if state.tune_periods < AUTO_TUNE_WINDOW {
+            return;
+        }
+
+        let drained = state.tune_drained * 100 / state.tune_periods;
+        state.bytes_per_sec = match drained {
+            90.. => (state.bytes_per_sec + state.bytes_per_sec / 20).max(state.bytes_per_sec + 1),
+            0..50 => state.bytes_per_sec - state.bytes_per_sec / 20,
+            _ => state.bytes_per_sec,
+        }
+        .clamp(auto_tune_min(state.max_bytes_per_sec), state.max_bytes_per_sec);
+        state.tune_periods = 0;
+        state.tune_drained = 0;
+    }
+
+    /// 状態はカウンタとキューだけなので、パニックした後も使い続けてよい
+    fn lock(&self) -> MutexGuard<'_, RateLimiterState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// 自動調整の下限
+fn auto_tune_min(max_bytes_per_sec: u64) -> u64 {
+    (max_bytes_per_sec / AUTO_TUNE_MIN_DIVISOR).max(1)
+}
+
+/// 1回の補充量（少なくとも1バイト）
+fn refill_bytes(bytes_per_sec: u64, refill_period: Duration) -> u64 {
+    ((bytes_per_sec as u128 * refill_period.as_micros() / 1_000_000) as u64).max(1)
+}
+
+impl std::fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("bytes_per_sec", &self.bytes_per_second())
+            .field("refill_period", &self.refill_period)
+            .field("fairness", &self.fairness)
+            .field("auto_tuned", &self.auto_tuned)
+            .finish()
+    }
+}
+
+/// Optionsの比較では同じRateLimiterを共有しているかを見る
+impl PartialEq for RateLimiter {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// 書き込む前にRateLimiterからトークンを得る書き出し先
+pub(crate) struct RateLimitedFile {
+    inner: Box<dyn WritableFile>,
+    limiter: Arc<RateLimiter>,
+    priority: IoPriority,
+}
+
+impl RateLimitedFile {
+    pub(crate) fn new(inner: Box<dyn WritableFile>, limiter: Arc<RateLimiter>, priority: IoPriority) -> Self {
+        Self {
+            inner,
+            limiter,
+            priority,
+        }
+    }
+}
+
+impl Write for RateLimitedFile {
+    /// 1回の補充量を超える分は書かずに返す（write_allが残りを続けて書く）
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = (buf.len() as u64).min(self.limiter.single_burst_bytes()) as usize;
+        self.limiter.request(len as u64, self.priority);
+        self.inner.write(&buf[..len])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl WritableFile for RateLimitedFile {
+    fn finish(&mut self) -> io::Result<()> {
+        self.inner.finish()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.inner.sync()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 補充は時刻によらずrefill()を直接呼んで進め、トークンの割り当てを確かめる
+
+    #[test]
+    fn test_limits_rate() {
+        // 10msごとに1000バイト
+        let limiter = RateLimiter::with_options(100_000, Duration::from_millis(10), 10, false);
+        assert_eq!(limiter.single_burst_bytes(), 1000);
+        let mut state = limiter.lock();
+
+        // 最初の1回分はすぐに使える
+        assert_eq!(limiter.enqueue(&mut state, 600, IoPriority::High), None);
+        assert_eq!(state.available, 400);
+        // 足りない要求は補充を待ち、後の要求は先の要求を追い越さない
+        let first = limiter.enqueue(&mut state, 1000, IoPriority::High).unwrap();
+        let second = limiter.enqueue(&mut state, 300, IoPriority::High).unwrap();
+        assert_eq!(state.available, 400);
+
+        // 1回の補充で1000バイト分だけ割り当てる
+        limiter.refill(&mut state, Instant::now());
+        assert!(state.granted.contains(&first) && !state.granted.contains(&second));
+        assert_eq!(state.available, 0);
+        limiter.refill(&mut state, Instant::now());
+        assert!(state.granted.contains(&second));
+        assert_eq!(state.available, 700);
+
+        // 1回の補充量を超える要求は切り詰める
+        assert!(limiter.enqueue(&mut state, 5000, IoPriority::High).is_some());
+        assert_eq!(state.total_bytes, [0, 2900]);
+        assert_eq!(state.total_requests, [0, 4]);
+    }
+
+    #[test]
+    fn test_high_priority_is_served_first() {
+        // 3回に1回はLowを先にする
+        let limiter = RateLimiter::with_options(20_000, Duration::from_millis(50), 3, false);
+        let mut state = limiter.lock();
+        // トークンを使い切っておく
+        assert_eq!(limiter.enqueue(&mut state, 1000, IoPriority::High), None);
+
+        let low = limiter.enqueue(&mut state, 1000, IoPriority::Low).unwrap();
+        let high = limiter.enqueue(&mut state, 1000, IoPriority::High).unwrap();
+        // 後から来たHighが先にトークンを得る
+        limiter.refill(&mut state, Instant::now());
+        assert!(state.granted.contains(&high) && !state.granted.contains(&low));
+        limiter.refill(&mut state, Instant::now());
+        assert!(state.granted.contains(&low));
+
+        // fairness回目の補充ではLowが先になる
+        let high = limiter.enqueue(&mut state, 1000, IoPriority::High).unwrap();
+        let low = limiter.enqueue(&mut state, 1000, IoPriority::Low).unwrap();
+        limiter.refill(&mut state, Instant::now());
+        assert_eq!(state.refills, 3);
+        assert!(state.granted.contains(&low) && !state.granted.contains(&high));
+    }
+
+    #[test]
+    fn test_auto_tune_raises_rate_under_load() {
+        let limiter = RateLimiter::with_options(10_000_000, Duration::from_millis(2), 10, true);
+        assert_eq!(limiter.bytes_per_second(), 5_000_000);
+        let mut state = limiter.lock();
+        let burst = refill_bytes(state.bytes_per_sec, limiter.refill_period);
+        assert_eq!(limiter.enqueue(&mut state, burst, IoPriority::Low), None);
+
+        // 毎回の補充で要求が待っていれば、AUTO_TUNE_WINDOW回ごとに5%上げる
+        for _ in 0..AUTO_TUNE_WINDOW {
+            assert_eq!(state.bytes_per_sec, 5_000_000);
+            limiter.enqueue(&mut state, burst, IoPriority::Low).unwrap();
+            limiter.refill(&mut state, Instant::now());
+        }
+        assert_eq!(state.bytes_per_sec, 5_250_000);
+
+        // 待ちがなければ5%下げる
+        for _ in 0..AUTO_TUNE_WINDOW {
+            limiter.refill(&mut state, Instant::now());
+        }
+        assert_eq!(state.bytes_per_sec, 4_987_500);
+
+        // 上限は超えない
+        state.bytes_per_sec = 9_900_000;
+        for _ in 0..AUTO_TUNE_WINDOW {
+            limiter.enqueue(&mut state, u64::MAX, IoPriority::Low);
+            limiter.enqueue(&mut state, u64::MAX, IoPriority::Low);
+            limiter.refill(&mut state, Instant::now());
+        }
+        assert_eq!(state.bytes_per_sec, 10_000_000);
+    }
+
+    #[test]
+    fn test_request_waits_for_refill() {
+        let limiter = RateLimiter::with_options(100_000, Duration::from_millis(10), 10, false);
+        for _ in 0..3 {
+            limiter.request(1000, IoPriority::High);
+        }
+        assert_eq!(limiter.total_bytes_through(IoPriority::High), 3000);
+        assert_eq!(limiter.total_requests(IoPriority::Low), 0);
+    }
+}
@@ -0,0 +1,270 @@
+use super::format::{get_varint32, put_varint32};
+use crate::error::{Error, Result};
+
+/// ブロックの組み立て
+///
+/// キーは直前のキーとの共通部分を省いて書く。restart_intervalエントリごとに
+/// キーを省略せずに書く位置（restart point）を置き、末尾にその一覧を書く
+///
+/// フォーマット:
+/// ```text
+/// entry:   [shared: varint][non_shared: varint][value_len: varint][key_delta][value]
+/// trailer: [restart: u32]... [num_restarts: u32]
+/// ```
+pub(crate) struct BlockBuilder {
+    buf: Vec<u8>,
+    restart_interval: usize,
+    restarts: Vec<u32>,
+    /// 最後のrestart point以降のエントリ数
+    counter: usize,
+    last_key: Vec<u8>,
+}
+
+impl BlockBuilder {
+    pub(crate) fn new(restart_interval: usize) -> Self {
+        assert!(restart_interval >= 1);
+        Self {
+            buf: Vec::new(),
+            restart_interval,
+            restarts: vec![0],
+            counter: 0,
+            last_key: Vec::new(),
+        }
+    }
+
+    /// エントリを追加する（キーは昇順で渡す）
+    pub(crate) fn add(&mut self, key: &[u8], value: &[u8]) {
+        debug_assert!(self.buf.is_empty() || key > self.last_key.as_slice());
+
+        let shared = if self.counter < self.restart_interval {
+            key.iter().zip(&self.last_key).take_while(|(a, b)| a == b).count()
+        } else {
+            self.restarts.push(self.buf.len() as u32);
+            self.counter = 0;
+            0
+        };
+
+        put_varint32(&mut self.buf, shared as u32);
+        put_varint32(&mut self.buf, (key.len() - shared) as u32);
+        put_varint32(&mut self.buf, value.len() as u32);
+        self.buf.extend_from_slice(&key[shared..]);
+        self.buf.extend_from_slice(value);
+
+        self.last_key.truncate(shared);
+        self.last_key.extend_from_slice(&key[shared..]);
+        self.counter += 1;
+    }
+
+    /// 現在のブロックのサイズ（restart pointの一覧を含む）
+    pub(crate) fn estimated_size(&self) -> usize {
+        self.buf.len() + (self.restarts.len() + 1) * 4
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// 組み立てたブロックを取り出して空に戻す
+    pub(crate) fn finish(&mut self) -> Vec<u8> {
+        for restart in &self.restarts {
+            self.buf.extend_from_slice(&restart.to_le_bytes());
+        }
+        self.buf.extend_from_slice(&(self.restarts.len() as u32).to_le_bytes());
+
+        self.restarts = vec![0];
+        self.counter = 0;
+        self.last_key.clear();
+        std::mem::take(&mut self.buf)
+    }
+}
+
+/// 展開済みのブロック
+pub(crate) struct Block<'a> {
+    /// エントリ部分（restart pointの一覧を除く）
+    entries: &'a [u8],
+    restarts: &'a [u8],
+}
+
+impl<'a> Block<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Result<Self> {
+        if data.len() < 4 {
+            return Err(Error::Corruption(format!("block too short: {} bytes", data.len())));
+        }
+        let num_restarts = u32::from_le_bytes(data[data.len() - 4..].try_into().unwrap()) as usize;
+        let restarts_len = num_restarts
+            .checked_mul(4)
+            .filter(|&len| num_restarts > 0 && len + 4 <= data.len())
+            .ok_or_else(|| Error::Corruption(format!("bad number of restart points: {}", num_restarts)))?;
+
+        let entries_end = data.len() - 4 - restarts_len;
+        Ok(Self {
+            entries: &data[..entries_end],
+            restarts: &data[entries_end..data.len() - 4],
+        })
+    }
+
+    /// 先頭から順に読むイテレータ
+    pub(crate) fn iter(&self) -> BlockIter<'a> {
+        BlockIter {
+            data: self.entries,
+            pos: 0,
+            key: Vec::new(),
+        }
+    }
+
+    /// target以上の最初のエントリを返す
+    ///
+    /// restart pointを二分探索して、target未満の最後のrestart pointから線形に探す
+    pub(crate) fn seek(&self, target: &[u8]) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let num_restarts = self.restarts.len() / 4;
+        let mut left = 0;
+        let mut right = num_restarts - 1;
+        while left < right {
+            let mid = (left + right).div_ceil(2);
+            let mut iter = self.iter_from(self.restart_point(mid)?);
+            let key = match iter.next() {
+                Some(entry) => entry?.0,
+                None => return Err(Error::Corruption("restart point past the end of block".to_string())),
+            };
+            if key.as_slice() < target {
+                left = mid;
+            } else {
+                right = mid - 1;
+            }
+        }
+
+        for entry in self.iter_from(self.restart_point(left)?) {
+            let (key, value) = entry?;
+            if key.as_slice() >= target {
+                return Ok(Some((key, value)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn restart_point(&self, index: usize) -> Result<usize> {
+        let offset = u32::from_le_bytes(self.restarts[index * 4..index * 4 + 4].try_into().unwrap()) as usize;
+        if offset > self.entries.len() {
+            return Err(Error::Corruption(format!("restart point out of range: {}", offset)));
+        }
+        Ok(offset)
+    }
+
+    fn iter_from(&self, pos: usize) -> BlockIter<'a> {
+        BlockIter {
+            data: self.entries,
+            pos,
+            key: Vec::new(),
+        }
+    }
+}
+
+/// ブロック内のエントリを順に読む
+pub(crate) struct BlockIter<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// 直前のエントリのキー（次のキーの共通部分の復元に使う）
+    key: Vec<u8>,
+}
+
+impl BlockIter<'_> {
+    fn read_varint(&mut self) -> Result<usize> {
+        let (value, len) = get_varint32(&self.data[self.pos..])
+            .ok_or_else(|| Error::Corruption("truncated block entry length".to_string()))?;
+        self.pos += len;
+        Ok(value as usize)
+    }
+
+    fn read_entry(&mut self) -> Result<(Vec<u8>, Vec<u8>)> {
+        let shared = self.read_varint()?;
+        let non_shared = self.read_varint()?;
+        let value_len = self.read_varint()?;
+        if shared > self.key.len() {
+            return Err(Error::Corruption(format!(
+                "shared key length {} exceeds previous key length {}",
+                shared,
+                self.key.len()
+            )));
+        }
+        let key_end = self.pos + non_shared;
+        let value_end = key_end + value_len;
+        if value_end > self.data.len() {
+            return Err(Error::Corruption("truncated block entry".to_string()));
+        }
+
+        self.key.truncate(shared);
+        self.key.extend_from_slice(&self.data[self.pos..key_end]);
+        self.pos = value_end;
+        Ok((self.key.clone(), self.data[key_end..value_end].to_vec()))
+    }
+}
+
+impl Iterator for BlockIter<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.data.len() {
+            return None;
+        }
+        let entry = self.read_entry();
+        if entry.is_err() {
+            // 壊れたブロックはそれ以上読まない
+            self.pos = self.data.len();
+        }
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_block(restart_interval: usize, entries: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+        let mut builder = BlockBuilder::new(restart_interval);
+        for (key, value) in entries {
+            builder.add(key, value);
+        }
+        builder.finish()
+    }
+
+    #[test]
+    fn test_prefix_compression_and_seek() {
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..100u64)
+            .map(|i| (format!("{:016}", i * 2).into_bytes(), format!("value{}", i).into_bytes()))
+            .collect();
+        let data = build_block(16, &entries);
+        // 16桁のキーをそのまま書くよりも小さい
+        assert!(data.len() < entries.len() * 16);
+
+        let block = Block::new(&data).unwrap();
+        assert_eq!(block.iter().collect::<Result<Vec<_>>>().unwrap(), entries);
+
+        for (i, (key, value)) in entries.iter().enumerate() {
+            assert_eq!(block.seek(key).unwrap(), Some((key.clone(), value.clone())));
+            // 存在しない奇数のキーは次のエントリに位置する
+            let odd = format!("{:016}", i * 2 + 1).into_bytes();
+            assert_eq!(block.seek(&odd).unwrap(), entries.get(i + 1).cloned());
+        }
+        assert_eq!(block.seek(b"").unwrap(), Some(entries[0].clone()));
+    }
+
+    #[test]
+    fn test_empty_block() {
+        let data = BlockBuilder::new(16).finish();
+        let block = Block::new(&data).unwrap();
+        assert!(block.iter().next().is_none());
+        assert_eq!(block.seek(b"key").unwrap(), None);
+    }
+
+    #[test]
+    fn test_corrupted_block() {
+        assert!(matches!(Block::new(&[1, 0]), Err(Error::Corruption(_))));
+
+        let entries = vec![(b"key1".to_vec(), b"value1".to_vec())];
+        let mut data = build_block(16, &entries);
+        // エントリのvalue_lenを壊す
+        data[2] = 0x7f;
+        let block = Block::new(&data).unwrap();
+        assert!(matches!(block.iter().next(), Some(Err(Error::Corruption(_)))));
+    }
+}
@@ -0,0 +1,193 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::lru::LruCache;
+
+/// ブロックキャッシュのシャード数のデフォルト（2^4 = 16）
+pub(crate) const DEFAULT_BLOCK_CACHE_SHARD_BITS: u32 = 4;
+
+/// キャッシュするブロックの種類（統計を分けるため）
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BlockKind {
+    Data,
+    Index,
+}
+
+/// ブロックキャッシュのキー（TableReaderごとのID + ブロックのoffset）
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey {
+    /// `BlockCache::new_id`で割り当てたID
+    pub(crate) cache_id: u64,
+    pub(crate) offset: u64,
+}
+
+/// ブロックキャッシュの統計
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockCacheStats {
+    pub data_hits: u64,
+    pub data_misses: u64,
+    pub index_hits: u64,
+    pub index_misses: u64,
+    /// キャッシュしているブロックの合計サイズ
+    pub usage: usize,
+    pub capacity: usize,
+}
+
+/// 展開済みのブロックをキャッシュするシャード分割したLRUキャッシュ
+///
+/// 複数のTableReaderで共有し、同じブロックを何度も読み込んで展開しないようにする。
+/// 容量はブロックのバイト数で数え、シャードごとに均等に割り当てる
+pub struct BlockCache {
+    shards: Vec<Mutex<LruShard>>,
+    shard_bits: u32,
+    capacity: usize,
+    data_hits: AtomicU64,
+    data_misses: AtomicU64,
+    index_hits: AtomicU64,
+    index_misses: AtomicU64,
+    /// 次に割り当てるキャッシュID
+    next_id: AtomicU64,
+}
+
+impl BlockCache {
+    /// 指定した容量（バイト）のキャッシュを作成
+    pub fn new(capacity: usize) -> Self {
+        Self::with_shard_bits(capacity, DEFAULT_BLOCK_CACHE_SHARD_BITS)
+    }
+
+    /// シャード数（2^shard_bits）を指定してキャッシュを作成
+    pub fn with_shard_bits(capacity: usize, shard_bits: u32) -> Self {
+        let num_shards = 1usize << shard_bits;
+        let shard_capacity = capacity.div_ceil(num_shards);
+        Self {
+            shards: (0..num_shards).map(|_| Mutex::new(LruShard::new(shard_capacity))).collect(),
+            shard_bits,
+            capacity,
+            data_hits: AtomicU64::new(0),
+            data_misses: AtomicU64::new(0),
+            index_hits: AtomicU64::new(0),
+            index_misses: AtomicU64::new(0),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// キャッシュを使うTableReaderに一意なIDを割り当てる
+    ///
+    /// ファイル番号はDBごとに0から振られるので、キャッシュを共有するとキーが衝突する
+    pub(crate) fn new_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// キャッシュからブロックを探す（見つかればLRUの先頭に移す）
+    pub(crate) fn lookup(&self, key: CacheKey, kind: BlockKind) -> Option<Arc<Vec<u8>>> {
+        let block = self.shard(key).lookup(key).cloned();
+        let counter = match (kind, block.is_some()) {
+            (BlockKind::Data, true) => &self.data_hits,
+            (BlockKind::Data, false) => &self.data_misses,
+            (BlockKind::Index, true) => &self.index_hits,
+            (BlockKind::Index, false) => &self.index_misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        block
+    }
+
+    /// ブロックをキャッシュに入れる（容量を超えた分は古いものから追い出す）
+    pub(crate) fn insert(&self, key: CacheKey, block: Arc<Vec<u8>>) {
+        let charge = block.len();
+        self.shard(key).insert(key, block, charge);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// キャッシュしているブロックの合計サイズ
+    pub fn usage(&self) -> usize {
+        self.shards.iter().map(|shard| lock(shard).usage()).sum()
+    }
+
+    pub fn stats(&self) -> BlockCacheStats {
+        BlockCacheStats {
+            data_hits: self.data_hits.load(Ordering::Relaxed),
+            data_misses: self.data_misses.load(Ordering::Relaxed),
+            index_hits: self.index_hits.load(Ordering::Relaxed),
+            index_misses: self.index_misses.load(Ordering::Relaxed),
+            usage: self.usage(),
+            capacity: self.capacity,
+        }
+    }
+
+    fn shard(&self, key: CacheKey) -> MutexGuard<'_, LruShard> {
+        if self.shard_bits == 0 {
+            return lock(&self.shards[0]);
+        }
+        // 上位ビットでシャードを選ぶ（ファイル内の連続したブロックも分散させる）
+        let hash = (key.cache_id.rotate_left(32) ^ key.offset).wrapping_mul(0x9e3779b97f4a7c15);
+        lock(&self.shards[(hash >> (64 - self.shard_bits)) as usize])
+    }
+}
+
+impl std::fmt::Debug for BlockCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockCache")
+            .field("capacity", &self.capacity)
+            .field("num_shards", &self.shards.len())
+            .finish()
+    }
+}
+
+/// 1つのシャード（chargeはブロックのバイト数）
+type LruShard = LruCache<CacheKey, Arc<Vec<u8>>>;
+
+/// キャッシュの中身は読み込んだブロックのコピーなので、パニックした後も使い続けてよい
+fn lock(shard: &Mutex<LruShard>) -> MutexGuard<'_, LruShard> {
+    shard.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(offset: u64) -> CacheKey {
+        CacheKey {
+            cache_id: 1,
+            offset,
+        }
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        // 1シャードで300バイト
+        let cache = BlockCache::with_shard_bits(300, 0);
+        for offset in 0..3 {
+            cache.insert(key(offset), Arc::new(vec![0u8; 100]));
+        }
+        assert_eq!(cache.usage(), 300);
+
+        // 0を使ってから4つ目を入れると、最も古い1が追い出される
+        assert!(cache.lookup(key(0), BlockKind::Data).is_some());
+        cache.insert(key(3), Arc::new(vec![0u8; 100]));
+        assert!(cache.lookup(key(1), BlockKind::Data).is_none());
+        assert!(cache.lookup(key(0), BlockKind::Data).is_some());
+        assert!(cache.lookup(key(2), BlockKind::Index).is_some());
+        assert_eq!(cache.usage(), 300);
+
+        // 容量より大きいブロックはキャッシュしない
+        cache.insert(key(4), Arc::new(vec![0u8; 400]));
+        assert!(cache.lookup(key(4), BlockKind::Data).is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.data_hits, stats.data_misses), (2, 2));
+        assert_eq!((stats.index_hits, stats.index_misses), (1, 0));
+    }
+
+    #[test]
+    fn test_sharded_capacity() {
+        let cache = BlockCache::new(16 * 1024);
+        for offset in 0..1000 {
+            cache.insert(key(offset * 4096), Arc::new(vec![0u8; 64]));
+        }
+        assert!(cache.usage() <= cache.capacity());
+        assert!(cache.usage() > cache.capacity() / 2);
+    }
+}
@@ -0,0 +1,217 @@
+use std::fmt;
+use std::str::FromStr;
+
+use zstd::dict::DecoderDictionary;
+
+use crate::error::{Error, Result};
+
+/// ブロックの圧縮方式
+///
+/// 値はtrailerに書かれる（RocksDBのCompressionTypeと同じ番号）
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompressionType {
+    /// 圧縮しない
+    #[default]
+    None,
+    /// Snappy（高速、圧縮率は低め）
+    Snappy,
+    /// LZ4（高速）
+    Lz4,
+    /// Zstandard（圧縮率重視）
+    Zstd,
+}
+
+/// Zstdの圧縮レベル（zstdのデフォルト）
+const ZSTD_LEVEL: i32 = 3;
+
+impl CompressionType {
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            CompressionType::None => 0,
+            CompressionType::Snappy => 1,
+            CompressionType::Lz4 => 4,
+            CompressionType::Zstd => 7,
+        }
+    }
+
+    pub(crate) fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(CompressionType::None),
+            1 => Ok(CompressionType::Snappy),
+            4 => Ok(CompressionType::Lz4),
+            7 => Ok(CompressionType::Zstd),
+            _ => Err(Error::Corruption(format!("unknown compression type: {}", value))),
+        }
+    }
+}
+
+impl fmt::Display for CompressionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CompressionType::None => "none",
+            CompressionType::Snappy => "snappy",
+            CompressionType::Lz4 => "lz4",
+            CompressionType::Zstd => "zstd",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for CompressionType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(CompressionType::None),
+            "snappy" => Ok(CompressionType::Snappy),
+            "lz4" => Ok(CompressionType::Lz4),
+            "zstd" => Ok(CompressionType::Zstd),
+            _ => Err(Error::InvalidArgument(format!("unknown compression type: {:?}", s))),
+        }
+    }
+}
+
+/// データブロックの圧縮
+///
+/// Zstdの場合は辞書を設定でき、同じファイルのすべてのデータブロックに同じ辞書を使う
+pub(crate) struct BlockCompressor {
+    compression_type: CompressionType,
+    min_ratio: f64,
+    /// 辞書を設定したZstdの圧縮器
+    zstd: Option<zstd::bulk::Compressor<'static>>,
+}
+
+impl BlockCompressor {
+    pub(crate) fn new(compression_type: CompressionType, min_ratio: f64) -> Self {
+        Self {
+            compression_type,
+            min_ratio,
+            zstd: None,
+        }
+    }
+
+    /// 以降のZstd圧縮に辞書を使う
+    pub(crate) fn set_dictionary(&mut self, dict: &[u8]) -> Result<()> {
+        self.zstd = Some(zstd::bulk::Compressor::with_dictionary(ZSTD_LEVEL, dict)?);
+        Ok(())
+    }
+
+    /// ブロックを圧縮する
+    ///
+    /// 圧縮後のサイズが`min_ratio`を満たさない場合は`None`を返す（生のブロックを書く）
+    pub(crate) fn compress(&mut self, raw: &[u8]) -> Result<Option<Vec<u8>>> {
+        let compressed = match self.compression_type {
+            CompressionType::None => return Ok(None),
+            CompressionType::Snappy => snap::raw::Encoder::new()
+                .compress_vec(raw)
+                .map_err(|e| Error::InvalidArgument(format!("snappy compression failed: {}", e)))?,
+            CompressionType::Lz4 => lz4_flex::block::compress_prepend_size(raw),
+            CompressionType::Zstd => match &mut self.zstd {
+                Some(compressor) => compressor.compress(raw)?,
+                None => zstd::bulk::compress(raw, ZSTD_LEVEL)?,
+            },
+        };
+
+        if compressed.is_empty() || (raw.len() as f64) < (compressed.len() as f64) * self.min_ratio {
+            return Ok(None);
+        }
+        Ok(Some(compressed))
+    }
+}
+
+/// サンプルからZstdの辞書を学習する
+///
+/// サンプルが少なすぎるなどで学習できなかった場合は`None`を返す（辞書なしで圧縮する）
+pub(crate) fn train_zstd_dictionary<S: AsRef<[u8]>>(samples: &[S], max_dict_bytes: usize) -> Option<Vec<u8>> {
+    zstd::dict::from_samples(samples, max_dict_bytes).ok()
+}
+
+/// trailerに記録された圧縮方式でブロックを展開する
+///
+/// Zstdのブロックはファイルに辞書があればそれを使って展開する。
+/// 辞書はブロックごとに読み込み直さないよう、TableReaderを開いたときに一度だけ準備しておく
+pub(crate) fn decompress_block(
+    compression_type: CompressionType,
+    data: &[u8],
+    zstd_dict: Option<&DecoderDictionary<'_>>,
+) -> Result<Vec<u8>> {
+    let corruption = |e: &dyn fmt::Display| {
+        Error::Corruption(format!("failed to decompress {} block: {}", compression_type, e))
+    };
+
+    match compression_type {
+        CompressionType::None => Ok(data.to_vec()),
+        CompressionType::Snappy => snap::raw::Decoder::new()
+            .decompress_vec(data)
+            .map_err(|e| corruption(&e)),
+        CompressionType::Lz4 => {
+            lz4_flex::block::decompress_size_prepended(data).map_err(|e| corruption(&e))
+        }
+        CompressionType::Zstd => match zstd_dict {
+            Some(dict) => {
+                let mut decoder =
+                    zstd::stream::Decoder::with_prepared_dictionary(data, dict).map_err(|e| corruption(&e))?;
+                let mut raw = Vec::new();
+                std::io::Read::read_to_end(&mut decoder, &mut raw).map_err(|e| corruption(&e))?;
+                Ok(raw)
+            }
+            None => zstd::stream::decode_all(data).map_err(|e| corruption(&e)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_roundtrip() {
+        let raw: Vec<u8> = (0..4096u32).flat_map(|i| format!("{:016}", i % 64).into_bytes()).collect();
+
+        for compression_type in [CompressionType::Snappy, CompressionType::Lz4, CompressionType::Zstd] {
+            let mut compressor = BlockCompressor::new(compression_type, 1.0);
+            let compressed = compressor.compress(&raw).unwrap().unwrap();
+            assert!(compressed.len() < raw.len());
+            assert_eq!(decompress_block(compression_type, &compressed, None).unwrap(), raw);
+
+            let name = compression_type.to_string();
+            assert_eq!(name.parse::<CompressionType>().unwrap(), compression_type);
+            assert_eq!(CompressionType::from_u8(compression_type.to_u8()).unwrap(), compression_type);
+        }
+    }
+
+    #[test]
+    fn test_min_ratio_keeps_raw_block() {
+        // 圧縮が効かないデータ
+        let mut state = 0x2545f4914f6cdd1du64;
+        let raw: Vec<u8> = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let mut compressor = BlockCompressor::new(CompressionType::Lz4, 1.125);
+        assert!(compressor.compress(&raw).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_zstd_dictionary_roundtrip() {
+        // 小さなブロックごとでは共通部分を活かせないデータ
+        let samples: Vec<Vec<u8>> = (0..200u32)
+            .map(|i| format!("{{\"id\":{:016},\"status\":\"active\",\"region\":\"ap-northeast-1\"}}", i).into_bytes())
+            .collect();
+        let dict = train_zstd_dictionary(&samples, 4096).unwrap();
+
+        let raw = &samples[7];
+        let mut plain = BlockCompressor::new(CompressionType::Zstd, 1.0);
+        let mut with_dict = BlockCompressor::new(CompressionType::Zstd, 1.0);
+        with_dict.set_dictionary(&dict).unwrap();
+
+        let compressed = with_dict.compress(raw).unwrap().unwrap();
+        assert!(plain.compress(raw).unwrap().is_none_or(|c| compressed.len() < c.len()));
+        let prepared = DecoderDictionary::copy(&dict);
+        assert_eq!(&decompress_block(CompressionType::Zstd, &compressed, Some(&prepared)).unwrap(), raw);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::error::{Error, Result};
+
+/// ブロックの後ろに付くtrailerのサイズ（圧縮方式1byte + CRC32 4byte）
+pub(crate) const BLOCK_TRAILER_SIZE: usize = 5;
+
+/// BlockHandleのエンコード後のサイズ（offset u64 + size u64）
+pub(crate) const BLOCK_HANDLE_SIZE: usize = 16;
+
+/// footerのサイズ（BlockHandle 2つ + マジックナンバー）
+pub(crate) const FOOTER_SIZE: usize = BLOCK_HANDLE_SIZE * 2 + 8;
+
+/// SSTableのマジックナンバー（"LSMWPSST"）
+pub(crate) const TABLE_MAGIC: u64 = u64::from_le_bytes(*b"LSMWPSST");
+
+/// Zstdの辞書を入れたメタブロックのmetaindexでの名前
+pub(crate) const COMPRESSION_DICT_BLOCK_NAME: &[u8] = b"compression.dict";
+
+/// ファイル内のブロックの位置（trailerを含まないサイズ）
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct BlockHandle {
+    pub(crate) offset: u64,
+    pub(crate) size: u64,
+}
+
+impl BlockHandle {
+    pub(crate) fn encode_to(&self, dst: &mut Vec<u8>) {
+        dst.extend_from_slice(&self.offset.to_le_bytes());
+        dst.extend_from_slice(&self.size.to_le_bytes());
+    }
+
+    pub(crate) fn decode(src: &[u8]) -> Result<Self> {
+        if src.len() < BLOCK_HANDLE_SIZE {
+            return Err(Error::Corruption(format!(
+                "block handle too short: {} bytes",
+                src.len()
+            )));
+        }
+        Ok(Self {
+            offset: u64::from_le_bytes(src[0..8].try_into().unwrap()),
+            size: u64::from_le_bytes(src[8..16].try_into().unwrap()),
+        })
+    }
+}
+
+/// ファイル末尾の固定長footer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Footer {
+    pub(crate) metaindex: BlockHandle,
+    pub(crate) index: BlockHandle,
+}
+
+impl Footer {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut dst = Vec::with_capacity(FOOTER_SIZE);
+        self.metaindex.encode_to(&mut dst);
+        self.index.encode_to(&mut dst);
+        dst.extend_from_slice(&TABLE_MAGIC.to_le_bytes());
+        dst
+    }
+
+    pub(crate) fn decode(src: &[u8]) -> Result<Self> {
+        if src.len() != FOOTER_SIZE {
+            return Err(Error::Corruption(format!("footer size mismatch: {} bytes", src.len())));
+        }
+        let magic = u64::from_le_bytes(src[BLOCK_HANDLE_SIZE * 2..].try_into().unwrap());
+        if magic != TABLE_MAGIC {
+            return Err(Error::Corruption(format!("bad table magic number: {:#x}", magic)));
+        }
+        Ok(Self {
+            metaindex: BlockHandle::decode(&src[..BLOCK_HANDLE_SIZE])?,
+            index: BlockHandle::decode(&src[BLOCK_HANDLE_SIZE..BLOCK_HANDLE_SIZE * 2])?,
+        })
+    }
+}
+
+/// u32をvarint（LEB128）でエンコードして追加する
+pub(crate) fn put_varint32(dst: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        dst.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    dst.push(value as u8);
+}
+
+/// varintのu32を読み、値と読んだバイト数を返す（途中で切れていればNone）
+pub(crate) fn get_varint32(src: &[u8]) -> Option<(u32, usize)> {
+    let mut value = 0u32;
+    for (i, &byte) in src.iter().take(5).enumerate() {
+        value |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// ブロックの内容と圧縮方式のバイトに対するチェックサム
+pub(crate) fn block_checksum(contents: &[u8], compression_type: u8) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(contents);
+    hasher.update(&[compression_type]);
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint32_roundtrip() {
+        for value in [0, 1, 127, 128, 300, 16383, 16384, u32::MAX] {
+            let mut buf = Vec::new();
+            put_varint32(&mut buf, value);
+            assert_eq!(get_varint32(&buf), Some((value, buf.len())));
+            assert_eq!(get_varint32(&buf[..buf.len() - 1]), None);
+        }
+        assert_eq!(get_varint32(&[0x05, 0xff]), Some((5, 1)));
+    }
+}
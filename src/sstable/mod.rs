@@ -0,0 +1,32 @@
+//! ブロック形式のSSTable
+//!
+//! ファイルレイアウト:
+//!
+//! ```text
+//! [data block 0][trailer]
+//! ...
+//! [data block N-1][trailer]
+//! [metaindex block][trailer]
+//! [index block][trailer]
+//! [footer]
+//! ```
+//!
+//! - trailer: 圧縮方式(1byte) + CRC32(4byte)。ブロックごとに圧縮方式を記録するので、
+//!   圧縮方式の異なるブロックが混在していても読める
+//! - data block: キーの昇順に並べたエントリ。キーは直前のキーとの差分で書き、
+//!   一定間隔のrestart pointから二分探索できる（block.rs）
+//! - index block: 各データブロックの最後のキー -> BlockHandle
+//! - metaindex block: メタブロック名 -> BlockHandle
+//! - footer: metaindex/indexのBlockHandleとマジックナンバー（固定長）
+
+mod block;
+mod block_cache;
+mod compression;
+mod format;
+mod table_builder;
+mod table_reader;
+
+pub use block_cache::{BlockCache, BlockCacheStats};
+pub use compression::CompressionType;
+pub(crate) use table_builder::{TableBuilder, TableOptions};
+pub use table_reader::{TableIter, TableReader, TableReaderOptions};
@@ -0,0 +1,181 @@
+use std::io::Write;
+
+use super::block::BlockBuilder;
+use super::compression::{train_zstd_dictionary, BlockCompressor, CompressionType};
+use super::format::{block_checksum, BlockHandle, Footer, COMPRESSION_DICT_BLOCK_NAME};
+use crate::error::Result;
+
+/// 1つのSSTableを書き出すときの設定
+#[derive(Clone, Debug)]
+pub(crate) struct TableOptions {
+    /// データブロックの目安サイズ（非圧縮）
+    pub(crate) block_size: usize,
+    /// データブロックのrestart pointの間隔（エントリ数）
+    pub(crate) block_restart_interval: usize,
+    /// データブロックの圧縮方式
+    pub(crate) compression: CompressionType,
+    /// 圧縮後のサイズがこの比率（非圧縮/圧縮）に届かなければ生のブロックを書く
+    pub(crate) min_compression_ratio: f64,
+    /// Zstdの辞書の最大サイズ（0なら辞書を使わない）
+    pub(crate) zstd_max_dict_bytes: usize,
+    /// 辞書の学習に使うデータブロックの合計サイズ
+    pub(crate) zstd_max_train_bytes: usize,
+}
+
+impl TableOptions {
+    fn use_zstd_dictionary(&self) -> bool {
+        self.compression == CompressionType::Zstd && self.zstd_max_dict_bytes > 0
+    }
+}
+
+/// SSTableの書き出し
+///
+/// エントリをデータブロックに詰め、block_sizeに達するたびに
+/// 圧縮してwriterに書き出す。finish()でindex/metaindex/footerを書く
+///
+/// Zstdの辞書を使う場合は、学習に使うサンプルが集まるまで
+/// データブロックをメモリに溜め、辞書を作ってからまとめて書き出す
+pub(crate) struct TableBuilder<W: Write> {
+    writer: W,
+    options: TableOptions,
+    compressor: BlockCompressor,
+    /// writerに書き出したバイト数（次のブロックのoffset）
+    offset: u64,
+    data_block: BlockBuilder,
+    index_block: BlockBuilder,
+    /// 現在のデータブロックの最後のキー
+    last_key: Vec<u8>,
+    /// 辞書の学習待ちのデータブロック（非圧縮）と最後のキー
+    buffered_blocks: Vec<(Vec<u8>, Vec<u8>)>,
+    buffered_bytes: usize,
+    /// 辞書の学習待ちならtrue
+    buffering: bool,
+    /// 学習した辞書（finish()でメタブロックとして書く）
+    zstd_dict: Option<Vec<u8>>,
+}
+
+impl<W: Write> TableBuilder<W> {
+    pub(crate) fn new(writer: W, options: TableOptions) -> Self {
+        Self {
+            compressor: BlockCompressor::new(options.compression, options.min_compression_ratio),
+            buffering: options.use_zstd_dictionary(),
+            data_block: BlockBuilder::new(options.block_restart_interval),
+            // indexはエントリごとにrestart pointを置いて二分探索しやすくする
+            index_block: BlockBuilder::new(1),
+            writer,
+            options,
+            offset: 0,
+            last_key: Vec::new(),
+            buffered_blocks: Vec::new(),
+            buffered_bytes: 0,
+            zstd_dict: None,
+        }
+    }
+
+    /// エントリを追加する（キーは昇順で渡す）
+    pub(crate) fn add(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.data_block.add(key, value);
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+
+        if self.data_block.estimated_size() >= self.options.block_size {
+            self.flush_data_block()?;
+        }
+        Ok(())
+    }
+
+    /// 残りのブロックとfooterを書き出し、writerとファイルのサイズを返す
+    ///
+    /// writerのflush/syncは呼び出し側で行う
+    pub(crate) fn finish(mut self) -> Result<(W, u64)> {
+        if !self.data_block.is_empty() {
+            self.flush_data_block()?;
+        }
+        if self.buffering {
+            self.write_buffered_blocks()?;
+        }
+
+        let mut metaindex = BlockBuilder::new(1);
+        if let Some(dict) = self.zstd_dict.take() {
+            let handle = self.write_raw_block(&dict, CompressionType::None)?;
+            let mut encoded_handle = Vec::new();
+            handle.encode_to(&mut encoded_handle);
+            metaindex.add(COMPRESSION_DICT_BLOCK_NAME, &encoded_handle);
+        }
+        let metaindex_handle = self.write_raw_block(&metaindex.finish(), CompressionType::None)?;
+
+        let index = self.index_block.finish();
+        let index_handle = self.write_raw_block(&index, CompressionType::None)?;
+
+        let footer = Footer {
+            metaindex: metaindex_handle,
+            index: index_handle,
+        };
+        let footer = footer.encode();
+        self.writer.write_all(&footer)?;
+        Ok((self.writer, self.offset + footer.len() as u64))
+    }
+
+    fn flush_data_block(&mut self) -> Result<()> {
+        let raw = self.data_block.finish();
+        if self.buffering {
+            self.buffered_bytes += raw.len();
+            self.buffered_blocks.push((raw, self.last_key.clone()));
+            if self.buffered_bytes >= self.options.zstd_max_train_bytes {
+                self.write_buffered_blocks()?;
+            }
+            return Ok(());
+        }
+        let last_key = self.last_key.clone();
+        self.write_data_block(&raw, &last_key)
+    }
+
+    /// 溜めたデータブロックから辞書を学習し、辞書を使って書き出す
+    ///
+    /// 学習できなかった場合（サンプルが少ないなど）は辞書なしで圧縮する
+    fn write_buffered_blocks(&mut self) -> Result<()> {
+        self.buffering = false;
+        let blocks = std::mem::take(&mut self.buffered_blocks);
+        let samples: Vec<&[u8]> = blocks.iter().map(|(raw, _)| raw.as_slice()).collect();
+        if let Some(dict) = train_zstd_dictionary(&samples, self.options.zstd_max_dict_bytes) {
+            self.compressor.set_dictionary(&dict)?;
+            self.zstd_dict = Some(dict);
+        }
+
+        for (raw, last_key) in blocks {
+            self.write_data_block(&raw, &last_key)?;
+        }
+        Ok(())
+    }
+
+    fn write_data_block(&mut self, raw: &[u8], last_key: &[u8]) -> Result<()> {
+        let compressed = self.compressor.compress(raw)?;
+        let handle = match &compressed {
+            Some(compressed) => self.write_raw_block(compressed, self.options.compression)?,
+            None => self.write_raw_block(raw, CompressionType::None)?,
+        };
+
+        let mut encoded_handle = Vec::new();
+        handle.encode_to(&mut encoded_handle);
+        self.index_block.add(last_key, &encoded_handle);
+        Ok(())
+    }
+
+    /// ブロックをそのままtrailerと一緒に書き出す
+    fn write_raw_block(&mut self, contents: &[u8], compression: CompressionType) -> Result<BlockHandle> {
+        let compression_type = compression.to_u8();
+        let mut trailer = [0u8; 5];
+        trailer[0] = compression_type;
+        trailer[1..].copy_from_slice(&block_checksum(contents, compression_type).to_le_bytes());
+
+        self.writer.write_all(contents)?;
+        self.writer.write_all(&trailer)?;
+
+        let handle = BlockHandle {
+            offset: self.offset,
+            size: contents.len() as u64,
+        };
+        self.offset += (contents.len() + trailer.len()) as u64;
+        Ok(handle)
+    }
+}
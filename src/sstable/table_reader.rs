@@ -0,0 +1,675 @@
+use std::collections::VecDeque;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+
+use zstd::dict::DecoderDictionary;
+
+use super::block::Block;
+use super::block_cache::{BlockCache, BlockKind, CacheKey};
+use super::compression::{decompress_block, CompressionType};
+use super::format::{
+    block_checksum, BlockHandle, Footer, BLOCK_TRAILER_SIZE, COMPRESSION_DICT_BLOCK_NAME, FOOTER_SIZE,
+};
+use crate::error::{Error, Result};
+use crate::file_system::{default_file_system, FileOptions, FileSystem, MappedFile, RandomAccessFile};
+use crate::perf_context::{perf_add, PerfTimer};
+
+/// TableReaderの読み込み設定
+#[derive(Clone, Debug)]
+pub struct TableReaderOptions {
+    /// ファイルを開くファイルシステム
+    pub file_system: Arc<dyn FileSystem>,
+    /// 展開済みのブロックをキャッシュする（複数のTableReaderで共有する）
+    pub block_cache: Option<Arc<BlockCache>>,
+    /// index blockもブロックキャッシュに入れ、TableReaderでは保持しない
+    ///
+    /// falseならindex blockは開いたときに読んでTableReaderが持ち続ける
+    pub cache_index_blocks: bool,
+    /// cache_index_blocksのとき、index blockをキャッシュに入れた上でTableReaderが保持する
+    /// （L0のファイルで使う。追い出されても読み直さない）
+    pub pin_index_block: bool,
+    /// ファイルをmmapし、preadの代わりにマッピングから読む
+    ///
+    /// 非圧縮のブロックはコピーせずにマッピングを直接参照し、ブロックキャッシュも使わない
+    pub use_mmap: bool,
+    /// ページキャッシュを通さずに読む（Direct I/O）
+    ///
+    /// ファイルシステムが対応していなければ通常の読み込みになる。use_mmapとは併用できない
+    pub use_direct_reads: bool,
+}
+
+impl Default for TableReaderOptions {
+    fn default() -> Self {
+        Self {
+            file_system: default_file_system(),
+            block_cache: None,
+            cache_index_blocks: false,
+            pin_index_block: false,
+            use_mmap: false,
+            use_direct_reads: false,
+        }
+    }
+}
+
+/// SSTableの読み込み元
+enum TableFile {
+    /// Direct I/Oかどうかはファイルシステムが扱う
+    File(Box<dyn RandomAccessFile>),
+    /// マッピングはTableReaderと読み出したブロックで共有する。
+    /// ファイルが削除されても、最後の参照がなくなるまでマッピングは有効
+    Mmap(MappedFile),
+}
+
+impl TableFile {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        match self {
+            TableFile::File(file) => file.read_exact_at(buf, offset)?,
+            TableFile::Mmap(mmap) => {
+                let src = (offset as usize)
+                    .checked_add(buf.len())
+                    .and_then(|end| mmap.get(offset as usize..end))
+                    .ok_or_else(|| Error::Corruption(format!("read out of range: offset {}", offset)))?;
+                buf.copy_from_slice(src);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 読み込んだブロックの中身
+#[derive(Clone)]
+pub(crate) enum BlockContents {
+    Owned(Arc<Vec<u8>>),
+    /// mmapしたファイルの範囲（コピーしない）
+    Mapped {
+        mmap: MappedFile,
+        offset: usize,
+        len: usize,
+    },
+}
+
+impl Deref for BlockContents {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            BlockContents::Owned(data) => data,
+            BlockContents::Mapped { mmap, offset, len } => &mmap[*offset..*offset + *len],
+        }
+    }
+}
+
+/// SSTableの読み込み
+///
+/// 開くときにfooter、metaindex、index blockを読み、データブロックは必要になったときに読む
+pub struct TableReader {
+    file: TableFile,
+    /// ブロックキャッシュのキーに使うID（開くたびにキャッシュから割り当てる）
+    cache_id: u64,
+    options: TableReaderOptions,
+    /// ファイルのサイズ（BlockHandleが範囲内かを確かめる）
+    file_size: u64,
+    index_handle: BlockHandle,
+    /// 保持しているindex block（Noneなら毎回ブロックキャッシュから読む）
+    index_block: Option<BlockContents>,
+    /// データブロックの圧縮に使われたZstdの辞書（展開用に準備済み）
+    zstd_dict: Option<DecoderDictionary<'static>>,
+}
+
+impl TableReader {
+    /// SSTableファイルを開く（ブロックキャッシュなし）
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with(path, TableReaderOptions::default())
+    }
+
+    /// 読み込み設定を指定してSSTableファイルを開く
+    ///
+    /// ファイルがなければ`Error::NotFound`を返す
+    pub fn open_with<P: AsRef<Path>>(path: P, options: TableReaderOptions) -> Result<Self> {
+        let path = path.as_ref();
+        let fs = &options.file_system;
+        let not_found = |e: io::Error| match e.kind() {
+            io::ErrorKind::NotFound => Error::NotFound(format!("{}", path.display())),
+            _ => Error::Io(e),
+        };
+        // mmapできないファイルシステムでは通常の読み込みになる
+        let mapped = match options.use_mmap {
+            true => fs.map_file(path).map_err(not_found)?,
+            false => None,
+        };
+        let file = match mapped {
+            Some(mmap) => TableFile::Mmap(mmap),
+            None => {
+                let file_options = FileOptions {
+                    use_direct_reads: options.use_direct_reads && !options.use_mmap,
+                    ..Default::default()
+                };
+                TableFile::File(fs.new_random_access_file(path, &file_options).map_err(not_found)?)
+            }
+        };
+        let file_size = match &file {
+            TableFile::File(file) => file.size()?,
+            TableFile::Mmap(mmap) => mmap.len() as u64,
+        };
+        if file_size < FOOTER_SIZE as u64 {
+            return Err(Error::Corruption(format!(
+                "file is too short to be an sstable: {} bytes",
+                file_size
+            )));
+        }
+
+        let mut footer = [0u8; FOOTER_SIZE];
+        file.read_exact_at(&mut footer, file_size - FOOTER_SIZE as u64)?;
+        let footer = Footer::decode(&footer)?;
+
+        let cache_id = options.block_cache.as_ref().map_or(0, |cache| cache.new_id());
+        let mut reader = Self {
+            file,
+            cache_id,
+            options,
+            file_size,
+            index_handle: footer.index,
+            index_block: None,
+            zstd_dict: None,
+        };
+
+        let metaindex_block = reader.read_block(footer.metaindex)?;
+        for entry in Block::new(&metaindex_block)?.iter() {
+            let (name, handle) = entry?;
+            if name == COMPRESSION_DICT_BLOCK_NAME {
+                let dict = reader.read_block(BlockHandle::decode(&handle)?)?;
+                reader.zstd_dict = Some(DecoderDictionary::copy(&dict));
+            }
+        }
+
+        let cache_index = reader.options.cache_index_blocks && reader.options.block_cache.is_some();
+        if !cache_index {
+            reader.index_block = Some(BlockContents::Owned(Arc::new(reader.read_block(footer.index)?)));
+        } else if reader.options.pin_index_block {
+            reader.index_block = Some(reader.read_block_cached(footer.index, BlockKind::Index)?);
+        }
+        Ok(reader)
+    }
+
+    /// データブロックの数
+    pub fn num_data_blocks(&self) -> Result<usize> {
+        Ok(self.data_block_handles()?.len())
+    }
+
+    /// キーに対応する値を読む
+    ///
+    /// indexでデータブロックを決め、ブロック内はrestart pointを二分探索する
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        // 最後のキーがkey以上の最初のブロックにだけ含まれうる
+        let index_block = self.index_block()?;
+        let Some((_, handle)) = Block::new(&index_block)?.seek(key)? else {
+            return Ok(None);
+        };
+        let block = self.read_block_cached(BlockHandle::decode(&handle)?, BlockKind::Data)?;
+        match Block::new(&block)?.seek(key)? {
+            Some((found, value)) if found == key => Ok(Some(value)),
+            _ => Ok(None),
+        }
+    }
+
+    /// すべてのエントリをキーの順に読む
+    pub fn iter(&self) -> TableIter<'_> {
+        TableIter {
+            reader: self,
+            handles: None,
+            next_block: 0,
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// index blockを返す（保持していなければブロックキャッシュから読む）
+    fn index_block(&self) -> Result<BlockContents> {
+        match &self.index_block {
+            Some(block) => Ok(block.clone()),
+            None => self.read_block_cached(self.index_handle, BlockKind::Index),
+        }
+    }
+
+    /// index blockに並んだデータブロックのBlockHandle（ファイル内の順）
+    fn data_block_handles(&self) -> Result<Vec<BlockHandle>> {
+        let index_block = self.index_block()?;
+        Block::new(&index_block)?
+            .iter()
+            .map(|entry| BlockHandle::decode(&entry?.1))
+            .collect()
+    }
+
+    /// ブロックキャッシュを通してブロックを読む
+    ///
+    /// mmapしている場合、非圧縮のブロックはキャッシュを通さずマッピングを返す
+    fn read_block_cached(&self, handle: BlockHandle, kind: BlockKind) -> Result<BlockContents> {
+        if let TableFile::Mmap(mmap) = &self.file {
+            // 範囲外のBlockHandleはread_raw_blockがCorruptionにする
+            let trailer_offset = handle.offset.checked_add(handle.size).map(|end| end as usize);
+            if trailer_offset.and_then(|end| mmap.get(end)) == Some(&CompressionType::None.to_u8()) {
+                return Ok(self.read_raw_block(handle)?.1);
+            }
+        }
+
+        let Some(cache) = &self.options.block_cache else {
+            return Ok(BlockContents::Owned(Arc::new(self.read_block(handle)?)));
+        };
+        let key = CacheKey {
+            cache_id: self.cache_id,
+            offset: handle.offset,
+        };
+        let timer = PerfTimer::start();
+        let cached = cache.lookup(key, kind);
+        timer.stop(|ctx| &mut ctx.block_cache_lookup_nanos);
+        if let Some(block) = cached {
+            perf_add(|ctx| &mut ctx.block_cache_hit_count, 1);
+            return Ok(BlockContents::Owned(block));
+        }
+        let block = Arc::new(self.read_block(handle)?);
+        cache.insert(key, Arc::clone(&block));
+        Ok(BlockContents::Owned(block))
+    }
+
+    /// ブロックを読み、チェックサムを検証して展開する
+    pub(crate) fn read_block(&self, handle: BlockHandle) -> Result<Vec<u8>> {
+        let (compression, contents) = self.read_raw_block(handle)?;
+        let timer = PerfTimer::start();
+        let block = decompress_block(compression, &contents, self.zstd_dict.as_ref());
+        timer.stop(|ctx| &mut ctx.block_decompress_nanos);
+        block
+    }
+
+    /// ブロックを展開せずに読む（trailerの圧縮方式と一緒に返す）
+    ///
+    /// footerにはチェックサムがないので、壊れたBlockHandleで巨大なバッファを確保しないよう
+    /// 先にファイルの範囲内かを確かめる
+    pub(crate) fn read_raw_block(&self, handle: BlockHandle) -> Result<(CompressionType, BlockContents)> {
+        let in_range = handle
+            .offset
+            .checked_add(handle.size)
+            .and_then(|end| end.checked_add(BLOCK_TRAILER_SIZE as u64))
+            .is_some_and(|end| end <= self.file_size);
+        if !in_range {
+            return Err(Error::Corruption(format!(
+                "block handle out of range: offset {}, size {}, file size {}",
+                handle.offset, handle.size, self.file_size
+            )));
+        }
+        let len = handle.size as usize;
+        perf_add(|ctx| &mut ctx.block_read_count, 1);
+        perf_add(|ctx| &mut ctx.block_read_bytes, handle.size);
+        let timer = PerfTimer::start();
+        let (block, trailer) = match &self.file {
+            TableFile::File(_) => {
+                let mut buf = vec![0u8; len + BLOCK_TRAILER_SIZE];
+                self.file.read_exact_at(&mut buf, handle.offset)?;
+                let trailer: [u8; BLOCK_TRAILER_SIZE] = buf[len..].try_into().unwrap();
+                buf.truncate(len);
+                (BlockContents::Owned(Arc::new(buf)), trailer)
+            }
+            TableFile::Mmap(mmap) => {
+                let offset = handle.offset as usize;
+                let trailer = offset
+                    .checked_add(len)
+                    .and_then(|end| mmap.get(end..end.checked_add(BLOCK_TRAILER_SIZE)?))
+                    .ok_or_else(|| {
+                        Error::Corruption(format!("block handle out of range: offset {}", handle.offset))
+                    })?;
+                let trailer: [u8; BLOCK_TRAILER_SIZE] = trailer.try_into().unwrap();
+                let block = BlockContents::Mapped {
+                    mmap: Arc::clone(mmap),
+                    offset,
+                    len,
+                };
+                (block, trailer)
+            }
+        };
+        timer.stop(|ctx| &mut ctx.block_read_nanos);
+
+        let compression_type = trailer[0];
+        let expected = u32::from_le_bytes(trailer[1..5].try_into().unwrap());
+        if block_checksum(&block, compression_type) != expected {
+            return Err(Error::Corruption(format!(
+                "block checksum mismatch at offset {}",
+                handle.offset
+            )));
+        }
+
+        Ok((CompressionType::from_u8(compression_type)?, block))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn has_zstd_dict(&self) -> bool {
+        self.zstd_dict.is_some()
+    }
+}
+
+/// TableReaderのエントリを先頭から順に返すイテレータ
+pub struct TableIter<'a> {
+    reader: &'a TableReader,
+    /// データブロックの一覧（最初のnext()でindexから読む）
+    handles: Option<Vec<BlockHandle>>,
+    next_block: usize,
+    /// 読み込み済みのブロックの残りのエントリ
+    entries: VecDeque<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Iterator for TableIter<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.handles.is_none() {
+            match self.reader.data_block_handles() {
+                Ok(handles) => self.handles = Some(handles),
+                Err(e) => {
+                    self.handles = Some(Vec::new());
+                    return Some(Err(e));
+                }
+            }
+        }
+        let handles = self.handles.as_ref().unwrap();
+
+        while self.entries.is_empty() {
+            let handle = *handles.get(self.next_block)?;
+            self.next_block += 1;
+
+            let loaded = self
+                .reader
+                .read_block_cached(handle, BlockKind::Data)
+                .and_then(|block| Block::new(&block)?.iter().collect::<Result<VecDeque<_>>>());
+            match loaded {
+                Ok(entries) => self.entries = entries,
+                Err(e) => {
+                    // エラーの後は何も返さない
+                    self.next_block = handles.len();
+                    return Some(Err(e));
+                }
+            }
+        }
+        self.entries.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sstable::{TableBuilder, TableOptions};
+    use std::fs::File;
+    use std::io::BufWriter;
+
+    fn build_table(path: &Path, options: TableOptions, entries: &[(Vec<u8>, Vec<u8>)]) {
+        let file = File::create(path).unwrap();
+        let mut builder = TableBuilder::new(BufWriter::new(file), options);
+        for (key, value) in entries {
+            builder.add(key, value).unwrap();
+        }
+        builder.finish().unwrap().0.into_inner().unwrap();
+    }
+
+    #[test]
+    fn test_roundtrip_with_mixed_compression() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("000000.sst");
+
+        // 圧縮が効く値と効かない値を交互に並べ、ブロックごとに圧縮方式が変わるようにする
+        let mut state = 0x9e3779b97f4a7c15u64;
+        let mut random_value = || {
+            (0..400)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect::<Vec<u8>>()
+        };
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..2000u64)
+            .map(|i| {
+                let value = if (i / 200) % 2 == 0 { vec![b'x'; 400] } else { random_value() };
+                (format!("{:016}", i).into_bytes(), value)
+            })
+            .collect();
+
+        let options = TableOptions {
+            block_size: 4096,
+            block_restart_interval: 16,
+            compression: CompressionType::Lz4,
+            min_compression_ratio: 1.125,
+            zstd_max_dict_bytes: 0,
+            zstd_max_train_bytes: 0,
+        };
+        build_table(&path, options, &entries);
+
+        let reader = TableReader::open(&path).unwrap();
+        let read: Vec<_> = reader.iter().collect::<Result<_>>().unwrap();
+        assert_eq!(read, entries);
+        for (key, value) in entries.iter().step_by(97) {
+            assert_eq!(reader.get(key).unwrap().as_ref(), Some(value));
+        }
+        assert_eq!(reader.get(b"0000000000000000x").unwrap(), None);
+        assert_eq!(reader.get(b"9999999999999999").unwrap(), None);
+
+        let types: Vec<CompressionType> = reader
+            .data_block_handles()
+            .unwrap()
+            .into_iter()
+            .map(|handle| reader.read_raw_block(handle).unwrap().0)
+            .collect();
+        assert!(types.contains(&CompressionType::Lz4));
+        assert!(types.contains(&CompressionType::None));
+        assert!(std::fs::metadata(&path).unwrap().len() < (entries.len() * 424) as u64);
+    }
+
+    #[test]
+    fn test_zstd_dictionary() {
+        // 100バイトの値（ベンチマークと同じ）で、ブロック単位では圧縮が効きにくいデータ
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..4000u64)
+            .map(|i| {
+                let value = format!(
+                    "{{\"user_id\":{:08},\"name\":\"user-{:x}\",\"status\":\"active\",\"score\":{:05}}}",
+                    i,
+                    i.wrapping_mul(0x9e3779b97f4a7c15),
+                    i * 7 % 100000
+                );
+                (format!("key{:08}", i).into_bytes(), value.into_bytes())
+            })
+            .collect();
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut sizes = Vec::new();
+        for (name, max_dict_bytes) in [("plain.sst", 0), ("dict.sst", 16 * 1024)] {
+            let path = temp_dir.path().join(name);
+            let options = TableOptions {
+                block_size: 1024,
+                block_restart_interval: 16,
+                compression: CompressionType::Zstd,
+                min_compression_ratio: 1.0,
+                zstd_max_dict_bytes: max_dict_bytes,
+                zstd_max_train_bytes: 128 * 1024,
+            };
+            build_table(&path, options, &entries);
+
+            let reader = TableReader::open(&path).unwrap();
+            assert_eq!(reader.has_zstd_dict(), max_dict_bytes > 0);
+            let read: Vec<_> = reader.iter().collect::<Result<_>>().unwrap();
+            assert_eq!(read, entries);
+            sizes.push(std::fs::metadata(&path).unwrap().len());
+        }
+        // 辞書を含めてもファイルは小さくなる
+        assert!(sizes[1] < sizes[0], "{:?}", sizes);
+    }
+
+    #[test]
+    fn test_block_cache_shared_between_readers() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let options = TableOptions {
+            block_size: 4096,
+            block_restart_interval: 16,
+            compression: CompressionType::Snappy,
+            min_compression_ratio: 1.125,
+            zstd_max_dict_bytes: 0,
+            zstd_max_train_bytes: 0,
+        };
+        // 同じキーで値だけが違う（ブロックのoffsetも同じになる）2つのファイル
+        for (name, value) in [("a.sst", b'a'), ("b.sst", b'b')] {
+            let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..1000u64)
+                .map(|i| (format!("{:016}", i).into_bytes(), vec![value; 100]))
+                .collect();
+            build_table(&temp_dir.path().join(name), options.clone(), &entries);
+        }
+
+        let cache = Arc::new(BlockCache::new(1024 * 1024));
+        let open = |name: &str, pin_index_block: bool| {
+            let options = TableReaderOptions {
+                block_cache: Some(Arc::clone(&cache)),
+                cache_index_blocks: true,
+                pin_index_block,
+                ..Default::default()
+            };
+            TableReader::open_with(temp_dir.path().join(name), options).unwrap()
+        };
+
+        let key = format!("{:016}", 500).into_bytes();
+        let a = open("a.sst", false);
+        for _ in 0..2 {
+            assert_eq!(a.get(&key).unwrap(), Some(vec![b'a'; 100]));
+        }
+        let stats = cache.stats();
+        assert_eq!((stats.data_hits, stats.data_misses), (1, 1));
+        assert_eq!((stats.index_hits, stats.index_misses), (1, 1));
+
+        // 別のTableReaderは別のキー。pinしたindexはキャッシュを引かない
+        let b = open("b.sst", true);
+        assert_eq!(cache.stats().index_misses, 2);
+        for _ in 0..3 {
+            assert_eq!(b.get(&key).unwrap(), Some(vec![b'b'; 100]));
+        }
+        assert_eq!(a.get(&key).unwrap(), Some(vec![b'a'; 100]));
+        let stats = cache.stats();
+        assert_eq!((stats.data_hits, stats.data_misses), (4, 2));
+        assert_eq!((stats.index_hits, stats.index_misses), (2, 2));
+        assert!(stats.usage > 0 && stats.usage <= stats.capacity);
+    }
+
+    #[test]
+    fn test_mmap_reads_outlive_deleted_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..1000u64)
+            .map(|i| (format!("{:016}", i).into_bytes(), format!("value{}", i).into_bytes()))
+            .collect();
+
+        for compression in [CompressionType::None, CompressionType::Lz4] {
+            let path = temp_dir.path().join(format!("{}.sst", compression));
+            let options = TableOptions {
+                block_size: 4096,
+                block_restart_interval: 16,
+                compression,
+                min_compression_ratio: 1.0,
+                zstd_max_dict_bytes: 0,
+                zstd_max_train_bytes: 0,
+            };
+            build_table(&path, options, &entries);
+
+            let cache = Arc::new(BlockCache::new(1024 * 1024));
+            let options = TableReaderOptions {
+                block_cache: Some(Arc::clone(&cache)),
+                use_mmap: true,
+                ..Default::default()
+            };
+            let reader = TableReader::open_with(&path, options).unwrap();
+            // コンパクションで削除されても、TableReaderが残っている間は読める
+            std::fs::remove_file(&path).unwrap();
+
+            let read: Vec<_> = reader.iter().collect::<Result<_>>().unwrap();
+            assert_eq!(read, entries);
+            assert_eq!(reader.get(&entries[123].0).unwrap(), Some(entries[123].1.clone()));
+
+            // 非圧縮のブロックはマッピングから直接読み、キャッシュに入れない
+            let uncompressed = compression == CompressionType::None;
+            assert_eq!(cache.usage() == 0, uncompressed);
+        }
+    }
+
+    #[test]
+    fn test_detects_corruption() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("000000.sst");
+        let entries = vec![(b"key1".to_vec(), b"value1".to_vec())];
+        let options = TableOptions {
+            block_size: 4096,
+            block_restart_interval: 16,
+            compression: CompressionType::None,
+            min_compression_ratio: 1.0,
+            zstd_max_dict_bytes: 0,
+            zstd_max_train_bytes: 0,
+        };
+        build_table(&path, options, &entries);
+
+        // データブロックの1バイトを壊す
+        let mut data = std::fs::read(&path).unwrap();
+        data[5] ^= 0xff;
+        std::fs::write(&path, &data).unwrap();
+
+        let reader = TableReader::open(&path).unwrap();
+        assert!(matches!(reader.iter().next(), Some(Err(Error::Corruption(_)))));
+        let options = TableReaderOptions {
+            use_mmap: true,
+            ..Default::default()
+        };
+        let reader = TableReader::open_with(&path, options).unwrap();
+        assert!(matches!(reader.iter().next(), Some(Err(Error::Corruption(_)))));
+
+        // footerが壊れていれば開けない
+        let len = data.len();
+        data[len - 1] ^= 0xff;
+        std::fs::write(&path, &data).unwrap();
+        assert!(matches!(TableReader::open(&path), Err(Error::Corruption(_))));
+    }
+
+    #[test]
+    fn test_rejects_out_of_range_block_handle() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("000000.sst");
+        let entries = vec![(b"key1".to_vec(), b"value1".to_vec())];
+        let options = TableOptions {
+            block_size: 4096,
+            block_restart_interval: 16,
+            compression: CompressionType::None,
+            min_compression_ratio: 1.0,
+            zstd_max_dict_bytes: 0,
+            zstd_max_train_bytes: 0,
+        };
+        build_table(&path, options, &entries);
+        let data = std::fs::read(&path).unwrap();
+        let footer_offset = data.len() - FOOTER_SIZE;
+        let footer = Footer::decode(&data[footer_offset..]).unwrap();
+
+        // footerにはチェックサムがないので、index blockのサイズを書き換えても開こうとする
+        let forged = [
+            BlockHandle { offset: footer.index.offset, size: 1 << 40 },
+            BlockHandle { offset: u64::MAX - 2, size: 1 },
+            BlockHandle { offset: 1, size: u64::MAX },
+            BlockHandle { offset: u64::MAX, size: 1 },
+        ];
+        for index in forged {
+            let mut data = data.clone();
+            data.truncate(footer_offset);
+            data.extend_from_slice(&Footer { index, ..footer }.encode());
+            std::fs::write(&path, &data).unwrap();
+            // mmapしてindex blockをキャッシュ経由で読むと、先にtrailerの圧縮方式を見る
+            for (use_mmap, cache_index_blocks) in [(false, false), (true, false), (true, true)] {
+                let options = TableReaderOptions {
+                    use_mmap,
+                    block_cache: Some(Arc::new(BlockCache::new(1024 * 1024))),
+                    cache_index_blocks,
+                    pin_index_block: true,
+                    ..Default::default()
+                };
+                let result = TableReader::open_with(&path, options);
+                assert!(matches!(result, Err(Error::Corruption(_))), "{:?} {}", index, use_mmap);
+            }
+        }
+    }
+}
@@ -0,0 +1,401 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// 累積カウンタの種類
+///
+/// WALとコンパクションとbloom filterはまだないので、それらのカウンタは常に0
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Ticker {
+    /// putで書き込んだキーと値の合計バイト数
+    BytesWritten,
+    /// putで書き込んだキーの数
+    KeysWritten,
+    /// 完了したフラッシュの数
+    Flushes,
+    /// フラッシュで書き出したSSTableの合計バイト数
+    FlushBytesWritten,
+    /// write stallで書き込みが待たされた合計時間（マイクロ秒）
+    StallMicros,
+    WalSyncs,
+    CompactionBytesRead,
+    CompactionBytesWritten,
+    /// bloom filterで読み込みを省けた回数
+    BloomFilterUseful,
+}
+
+impl Ticker {
+    pub const ALL: [Ticker; 9] = [
+        Ticker::BytesWritten,
+        Ticker::KeysWritten,
+        Ticker::Flushes,
+        Ticker::FlushBytesWritten,
+        Ticker::StallMicros,
+        Ticker::WalSyncs,
+        Ticker::CompactionBytesRead,
+        Ticker::CompactionBytesWritten,
+        Ticker::BloomFilterUseful,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Ticker::BytesWritten => "lsm.bytes.written",
+            Ticker::KeysWritten => "lsm.keys.written",
+            Ticker::Flushes => "lsm.flush.count",
+            Ticker::FlushBytesWritten => "lsm.flush.bytes.written",
+            Ticker::StallMicros => "lsm.stall.micros",
+            Ticker::WalSyncs => "lsm.wal.syncs",
+            Ticker::CompactionBytesRead => "lsm.compaction.bytes.read",
+            Ticker::CompactionBytesWritten => "lsm.compaction.bytes.written",
+            Ticker::BloomFilterUseful => "lsm.bloom.filter.useful",
+        }
+    }
+}
+
+/// 値の分布を記録するヒストグラムの種類
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Histogram {
+    /// putにかかった時間（write stallを含む、マイクロ秒）
+    PutMicros,
+    /// SSTableの書き出しにかかった時間（マイクロ秒）
+    FlushMicros,
+    /// フラッシュで書き出したSSTableのサイズ（バイト）
+    SstableSize,
+    /// write stall1回で待たされた時間（マイクロ秒）
+    StallMicros,
+}
+
+impl Histogram {
+    pub const ALL: [Histogram; 4] = [
+        Histogram::PutMicros,
+        Histogram::FlushMicros,
+        Histogram::SstableSize,
+        Histogram::StallMicros,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Histogram::PutMicros => "lsm.put.micros",
+            Histogram::FlushMicros => "lsm.flush.micros",
+            Histogram::SstableSize => "lsm.sst.size",
+            Histogram::StallMicros => "lsm.stall.duration.micros",
+        }
+    }
+}
+
+/// バケットの上限（バケットiには`limits[i-1] < v <= limits[i]`の値が入る）
+///
+/// 1.5倍ずつ大きくなるので、どの値でも誤差は半分程度に収まる
+fn bucket_limits() -> &'static [u64] {
+    static LIMITS: OnceLock<Vec<u64>> = OnceLock::new();
+    LIMITS.get_or_init(|| {
+        let mut limits = vec![1u64];
+        let mut limit = 1u64;
+        while limit < u64::MAX / 2 {
+            limit = (limit + 1).max(limit + limit / 2);
+            limits.push(limit);
+        }
+        limits.push(u64::MAX);
+        limits
+    })
+}
+
+fn bucket_index(value: u64) -> usize {
+    bucket_limits().partition_point(|&limit| limit < value)
+}
+
+/// 固定のバケットに値を数えるヒストグラム（ロックを取らずに記録できる）
+struct HistogramImpl {
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum: AtomicU64,
+    min: AtomicU64,
+    max: AtomicU64,
+}
+
+impl HistogramImpl {
+    fn new() -> Self {
+        Self {
+            buckets: bucket_limits().iter().map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            min: AtomicU64::new(u64::MAX),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    fn add(&self, value: u64) {
+        self.buckets[bucket_index(value)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.min.fetch_min(value, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        let count = self.count.load(Ordering::Relaxed);
+        HistogramSnapshot {
+            count,
+            sum: self.sum.load(Ordering::Relaxed),
+            min: if count == 0 { 0 } else { self.min.load(Ordering::Relaxed) },
+            max: self.max.load(Ordering::Relaxed),
+            buckets: self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).collect(),
+        }
+    }
+
+    fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.count.store(0, Ordering::Relaxed);
+        self.sum.store(0, Ordering::Relaxed);
+        self.min.store(u64::MAX, Ordering::Relaxed);
+        self.max.store(0, Ordering::Relaxed);
+    }
+}
+
+/// ヒストグラムのある時点の値
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    pub count: u64,
+    pub sum: u64,
+    /// 記録がなければ0
+    pub min: u64,
+    pub max: u64,
+    buckets: Vec<u64>,
+}
+
+impl HistogramSnapshot {
+    pub fn average(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.sum as f64 / self.count as f64
+    }
+
+    /// p（0〜100）パーセンタイルの推定値
+    ///
+    /// 該当するバケットの中で値が均等に分布しているとみなして補間し、min/maxの範囲に収める
+    pub fn percentile(&self, p: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let threshold = self.count as f64 * p.clamp(0.0, 100.0) / 100.0;
+        let limits = bucket_limits();
+        let mut cumulative = 0u64;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            if n == 0 {
+                continue;
+            }
+            let before = cumulative;
+            cumulative += n;
+            if cumulative as f64 >= threshold {
+                let low = if i == 0 { 0 } else { limits[i - 1] };
+                let high = limits[i];
+                let position = (threshold - before as f64) / n as f64;
+                let value = low as f64 + (high - low) as f64 * position;
+                return value.clamp(self.min as f64, self.max as f64);
+            }
+        }
+        self.max as f64
+    }
+
+    pub fn median(&self) -> f64 {
+        self.percentile(50.0)
+    }
+
+    /// バケットの上限と、その上限以下の値の数の累計（最後のバケットの上限はu64::MAX）
+    pub(crate) fn cumulative_buckets(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        bucket_limits().iter().zip(&self.buckets).scan(0, |cumulative, (&limit, &n)| {
+            *cumulative += n;
+            Some((limit, *cumulative))
+        })
+    }
+}
+
+impl fmt::Display for HistogramSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "count {} sum {} min {} avg {:.2} p50 {:.2} p95 {:.2} p99 {:.2} max {}",
+            self.count,
+            self.sum,
+            self.min,
+            self.average(),
+            self.median(),
+            self.percentile(95.0),
+            self.percentile(99.0),
+            self.max
+        )
+    }
+}
+
+/// 統計のある時点の値
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatisticsSnapshot {
+    tickers: Vec<u64>,
+    histograms: Vec<HistogramSnapshot>,
+}
+
+impl StatisticsSnapshot {
+    pub fn ticker(&self, ticker: Ticker) -> u64 {
+        self.tickers[ticker as usize]
+    }
+
+    pub fn histogram(&self, histogram: Histogram) -> &HistogramSnapshot {
+        &self.histograms[histogram as usize]
+    }
+}
+
+/// 1行に1つのカウンタまたはヒストグラムを出力する
+impl fmt::Display for StatisticsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for ticker in Ticker::ALL {
+            writeln!(f, "{} COUNT : {}", ticker.name(), self.ticker(ticker))?;
+        }
+        for histogram in Histogram::ALL {
+            writeln!(f, "{} {}", histogram.name(), self.histogram(histogram))?;
+        }
+        Ok(())
+    }
+}
+
+/// WritePathが更新するカウンタとヒストグラム（RocksDBのStatistics相当）
+///
+/// Optionsに指定したWritePathが更新する。複数のWritePathで共有すれば合計が得られる。
+/// 記録はロックを取らないので、どのスレッドからでも呼べる
+pub struct Statistics {
+    tickers: Vec<AtomicU64>,
+    histograms: Vec<HistogramImpl>,
+}
+
+impl Statistics {
+    pub fn new() -> Self {
+        Self {
+            tickers: Ticker::ALL.iter().map(|_| AtomicU64::new(0)).collect(),
+            histograms: Histogram::ALL.iter().map(|_| HistogramImpl::new()).collect(),
+        }
+    }
+
+    pub fn record_tick(&self, ticker: Ticker, count: u64) {
+        self.tickers[ticker as usize].fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn ticker(&self, ticker: Ticker) -> u64 {
+        self.tickers[ticker as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn record_in_histogram(&self, histogram: Histogram, value: u64) {
+        self.histograms[histogram as usize].add(value);
+    }
+
+    /// 経過時間をマイクロ秒でヒストグラムに記録する
+    pub(crate) fn record_micros(&self, histogram: Histogram, elapsed: Duration) {
+        self.record_in_histogram(histogram, elapsed.as_micros() as u64);
+    }
+
+    pub fn histogram(&self, histogram: Histogram) -> HistogramSnapshot {
+        self.histograms[histogram as usize].snapshot()
+    }
+
+    /// すべてのカウンタとヒストグラムの値を取得する
+    ///
+    /// 記録と並行して呼ぶと、カウンタの間で少しずれることがある
+    pub fn snapshot(&self) -> StatisticsSnapshot {
+        StatisticsSnapshot {
+            tickers: Ticker::ALL.iter().map(|&t| self.ticker(t)).collect(),
+            histograms: Histogram::ALL.iter().map(|&h| self.histogram(h)).collect(),
+        }
+    }
+
+    /// すべてのカウンタとヒストグラムを0に戻す
+    pub fn reset(&self) {
+        for ticker in &self.tickers {
+            ticker.store(0, Ordering::Relaxed);
+        }
+        for histogram in &self.histograms {
+            histogram.reset();
+        }
+    }
+}
+
+impl Default for Statistics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.snapshot().fmt(f)
+    }
+}
+
+impl fmt::Debug for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Statistics")
+            .field("tickers", &self.snapshot().tickers)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Optionsの比較では同じStatisticsを共有しているかを見る
+impl PartialEq for Statistics {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tickers_and_reset() {
+        let stats = Statistics::new();
+        stats.record_tick(Ticker::BytesWritten, 10);
+        stats.record_tick(Ticker::BytesWritten, 5);
+        stats.record_tick(Ticker::KeysWritten, 2);
+        assert_eq!(stats.ticker(Ticker::BytesWritten), 15);
+
+        let snapshot = stats.snapshot();
+        stats.reset();
+        assert_eq!(snapshot.ticker(Ticker::KeysWritten), 2);
+        assert_eq!(stats.ticker(Ticker::BytesWritten), 0);
+        assert_eq!(stats.histogram(Histogram::PutMicros).count, 0);
+
+        let dump = snapshot.to_string();
+        assert!(dump.contains("lsm.bytes.written COUNT : 15\n"), "{}", dump);
+        assert!(dump.contains("lsm.put.micros count 0 "), "{}", dump);
+    }
+
+    #[test]
+    fn test_histogram_percentiles() {
+        let stats = Statistics::new();
+        for value in 1..=1000 {
+            stats.record_in_histogram(Histogram::FlushMicros, value);
+        }
+        let histogram = stats.histogram(Histogram::FlushMicros);
+        assert_eq!(histogram.count, 1000);
+        assert_eq!((histogram.min, histogram.max), (1, 1000));
+        assert_eq!(histogram.average(), 500.5);
+
+        // バケットの幅の分だけ誤差がある
+        let p50 = histogram.median();
+        assert!((400.0..=600.0).contains(&p50), "{}", p50);
+        let p99 = histogram.percentile(99.0);
+        assert!((900.0..=1000.0).contains(&p99), "{}", p99);
+        assert_eq!(histogram.percentile(100.0), 1000.0);
+    }
+
+    #[test]
+    fn test_bucket_limits() {
+        let limits = bucket_limits();
+        assert!(limits.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(bucket_index(0), 0);
+        assert_eq!(bucket_index(1), 0);
+        assert_eq!(bucket_index(2), 1);
+        assert_eq!(bucket_index(u64::MAX), limits.len() - 1);
+    }
+}
@@ -0,0 +1,141 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::error::Result;
+use crate::lru::LruCache;
+use crate::sstable::{TableReader, TableReaderOptions};
+
+/// SSTableのファイルパス
+pub(crate) fn table_file_path(data_dir: &Path, file_number: u64) -> PathBuf {
+    data_dir.join(format!("{:06}.sst", file_number))
+}
+
+/// 開いたSSTableのキャッシュ（RocksDBのTableCache相当）
+///
+/// ファイル番号ごとにTableReader（ファイルディスクリプタ、footer、index block、辞書）を
+/// 保持し、読み込みのたびにファイルを開き直さないようにする。
+/// `max_open_files`を超えたら最も長く使っていないものを閉じる
+pub struct TableCache {
+    data_dir: PathBuf,
+    reader_options: TableReaderOptions,
+    max_open_files: usize,
+    /// ファイル番号ごとのTableReader（chargeは1つにつき1）
+    tables: Mutex<LruCache<u64, Arc<TableReader>>>,
+}
+
+impl TableCache {
+    pub fn new<P: AsRef<Path>>(
+        data_dir: P,
+        reader_options: TableReaderOptions,
+        max_open_files: usize,
+    ) -> Self {
+        let max_open_files = max_open_files.max(1);
+        Self {
+            data_dir: data_dir.as_ref().to_path_buf(),
+            reader_options,
+            max_open_files,
+            tables: Mutex::new(LruCache::new(max_open_files)),
+        }
+    }
+
+    /// ファイル番号のTableReaderを返す（キャッシュになければ開いて入れる）
+    ///
+    /// 追い出されたTableReaderも、返したArcが残っている間は使える
+    pub fn find_table(&self, file_number: u64) -> Result<Arc<TableReader>> {
+        if let Some(reader) = self.lock().lookup(file_number) {
+            return Ok(Arc::clone(reader));
+        }
+
+        // ファイルを開く間は他のファイルの検索を止めない
+        let path = table_file_path(&self.data_dir, file_number);
+        let reader = Arc::new(TableReader::open_with(path, self.reader_options.clone())?);
+
+        let mut tables = self.lock();
+        // 他のスレッドが先に開いていればそちらを使う
+        if let Some(existing) = tables.lookup(file_number) {
+            return Ok(Arc::clone(existing));
+        }
+        tables.insert(file_number, Arc::clone(&reader), 1);
+        Ok(reader)
+    }
+
+    /// キャッシュからファイルを取り除く（コンパクションでファイルを削除するときに呼ぶ）
+    ///
+    /// 取り除いた後のfind_tableはファイルを開き直す。返したArcが残っていれば、そのTableReaderは使える
+    pub fn evict(&self, file_number: u64) {
+        self.lock().remove(file_number);
+    }
+
+    /// 開いているファイルの数
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// キャッシュの中身は開き直せるので、パニックした後も使い続けてよい
+    fn lock(&self) -> MutexGuard<'_, LruCache<u64, Arc<TableReader>>> {
+        self.tables.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl std::fmt::Debug for TableCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TableCache")
+            .field("data_dir", &self.data_dir)
+            .field("max_open_files", &self.max_open_files)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, Options, WritePath};
+
+    /// 1エントリずつ別のSSTableに書き出す
+    fn write_tables(data_dir: &Path, count: u64) {
+        let options = Options::builder().write_buffer_size(1).build().unwrap();
+        let write_path = WritePath::open(data_dir, options).unwrap();
+        for i in 0..count {
+            write_path
+                .put(format!("key{}", i).into_bytes(), format!("value{}", i).into_bytes())
+                .unwrap();
+        }
+        write_path.close().unwrap();
+    }
+
+    #[test]
+    fn test_find_table_reuses_open_readers() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        write_tables(temp_dir.path(), 3);
+
+        let cache = TableCache::new(temp_dir.path(), TableReaderOptions::default(), 2);
+        let first = cache.find_table(0).unwrap();
+        assert!(Arc::ptr_eq(&first, &cache.find_table(0).unwrap()));
+        assert_eq!(first.get(b"key0").unwrap(), Some(b"value0".to_vec()));
+
+        // max_open_filesを超えると最も長く使っていない1が閉じられる
+        cache.find_table(1).unwrap();
+        cache.find_table(0).unwrap();
+        cache.find_table(2).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(Arc::ptr_eq(&first, &cache.find_table(0).unwrap()));
+
+        // 開けなかったファイルはキャッシュに入れない
+        assert!(matches!(cache.find_table(3), Err(Error::NotFound(_))));
+        assert_eq!(cache.len(), 2);
+
+        // 削除したファイルは取り除けば開けなくなる
+        cache.evict(2);
+        std::fs::remove_file(table_file_path(temp_dir.path(), 2)).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert!(matches!(cache.find_table(2), Err(Error::NotFound(_))));
+        assert_eq!(cache.len(), 1);
+        // 取り除いた後のfind_tableは開き直す
+        cache.evict(0);
+        assert!(!Arc::ptr_eq(&first, &cache.find_table(0).unwrap()));
+    }
+}
@@ -0,0 +1,52 @@
+//! `tracing` featureのときだけspanを作る
+//!
+//! featureが無効なら`span!`は何もしない値になり、フィールドの式も評価されない
+
+/// 指定したレベルのspanを作って入る（スコープを抜けると閉じる）
+///
+/// 後から値を入れるフィールドは`tracing::field::Empty`で宣言し、`record`で設定する
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($level:ident, $name:expr $(, $($fields:tt)*)?) => {
+        tracing::span!(tracing::Level::$level, $name $(, $($fields)*)?).entered()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+    ($level:ident, $name:expr $(, $($fields:tt)*)?) => {
+        $crate::trace::NoopSpan
+    };
+}
+
+pub(crate) use span;
+
+/// spanのID（別のスレッドで続く処理のspanを`follows_from`で関連付けるために渡す）
+#[cfg(feature = "tracing")]
+pub(crate) type SpanId = Option<tracing::Id>;
+
+#[cfg(not(feature = "tracing"))]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SpanId;
+
+/// 関連付けるspanがないことを表すSpanId
+#[cfg(feature = "tracing")]
+pub(crate) const NO_SPAN: SpanId = None;
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) const NO_SPAN: SpanId = SpanId;
+
+/// featureが無効なときのspan
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoopSpan;
+
+#[cfg(not(feature = "tracing"))]
+impl NoopSpan {
+    pub(crate) fn record<V>(&self, _field: &str, _value: V) {}
+
+    pub(crate) fn id(&self) -> SpanId {
+        SpanId
+    }
+
+    pub(crate) fn follows_from(&self, _cause: SpanId) {}
+}
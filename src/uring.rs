@@ -0,0 +1,259 @@
+//! io_uringによるSSTableの書き出し（`io_uring` feature、Linuxのみ）
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+
+use io_uring::{opcode, types, IoUring};
+
+use crate::file_system::WritableFile;
+use crate::file_writer::range_sync;
+
+/// 書き込みバッファ1つのサイズ
+const URING_BUFFER_SIZE: usize = 256 * 1024;
+
+/// 同時にカーネルへ渡しておける書き込みの数
+const URING_NUM_BUFFERS: usize = 4;
+
+/// この数の書き込みが溜まったらまとめてsubmitする
+const URING_SUBMIT_BATCH: usize = 2;
+
+/// fsyncのuser_data（書き込みはバッファの番号を使う）
+const FSYNC_USER_DATA: u64 = u64::MAX;
+
+/// io_uringで書き込むファイル
+///
+/// バッファが埋まるたびに書き込みをキューに積み、複数の書き込みを並行して進める。
+/// 1つのスレッドのままI/Oの完了を待たずに次のブロックを組み立てられる。
+/// `bytes_per_sync`は完了した書き込みが先頭から連続して溜まった分をrange syncする
+pub(crate) struct UringFileWriter {
+    /// カーネルがまだ使っているかもしれないfileとbuffersより先に閉じる
+    ring: IoUring,
+    file: File,
+    buffers: Vec<Vec<u8>>,
+    /// カーネルが使っているバッファの書き込み先offset（Noneなら空き）
+    in_flight: Vec<Option<u64>>,
+    /// 今データを詰めているバッファ
+    current: usize,
+    /// 次の書き込み先offset
+    offset: u64,
+    /// キューに積んだがsubmitしていない数
+    unsubmitted: usize,
+    bytes_per_sync: u64,
+    /// range syncを発行済みの位置
+    synced_offset: u64,
+}
+
+impl UringFileWriter {
+    /// io_uringを使えない環境（カーネルが古い、seccompで禁止されているなど）ではエラーを返す
+    pub(crate) fn new(file: File, bytes_per_sync: u64) -> io::Result<Self> {
+        Ok(Self {
+            ring: IoUring::new((URING_NUM_BUFFERS + 1) as u32)?,
+            file,
+            buffers: (0..URING_NUM_BUFFERS)
+                .map(|_| Vec::with_capacity(URING_BUFFER_SIZE))
+                .collect(),
+            in_flight: vec![None; URING_NUM_BUFFERS],
+            current: 0,
+            offset: 0,
+            unsubmitted: 0,
+            bytes_per_sync,
+            synced_offset: 0,
+        })
+    }
+
+    /// キューに積んだ書き込みをsubmitし、want個の完了を待つ
+    ///
+    /// シグナルによる中断（EINTR）とカーネルの資源不足（EAGAIN）はやり直す
+    fn submit_and_wait(&mut self, want: usize) -> io::Result<()> {
+        loop {
+            match self.ring.submit_and_wait(want) {
+                Ok(_) => {
+                    self.unsubmitted = 0;
+                    return Ok(());
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// 現在のバッファの書き込みをキューに積み、空いているバッファに切り替える
+    fn queue_current(&mut self) -> io::Result<()> {
+        let buf = &self.buffers[self.current];
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let len = buf.len();
+        let entry = opcode::Write::new(types::Fd(self.file.as_raw_fd()), buf.as_ptr(), len as u32)
+            .offset(self.offset)
+            .build()
+            .user_data(self.current as u64);
+        self.push(&entry)?;
+
+        self.in_flight[self.current] = Some(self.offset);
+        self.offset += len as u64;
+        if self.unsubmitted >= URING_SUBMIT_BATCH {
+            self.submit_and_wait(0)?;
+        }
+
+        loop {
+            if let Some(free) = self.in_flight.iter().position(Option::is_none) {
+                self.current = free;
+                return Ok(());
+            }
+            self.wait_one()?;
+        }
+    }
+
+    fn push(&mut self, entry: &io_uring::squeue::Entry) -> io::Result<()> {
+        // SAFETY: バッファは完了を回収するまで変更も解放もしない（Dropでも完了を待ち、回収できなければリークさせる）
+        while unsafe { self.ring.submission().push(entry) }.is_err() {
+            self.submit_and_wait(0)?;
+        }
+        self.unsubmitted += 1;
+        Ok(())
+    }
+
+    /// 完了を1つ以上待って回収する
+    fn wait_one(&mut self) -> io::Result<()> {
+        self.submit_and_wait(1)?;
+        let completions: Vec<(u64, i32)> = self
+            .ring
+            .completion()
+            .map(|cqe| (cqe.user_data(), cqe.result()))
+            .collect();
+
+        let mut result = Ok(());
+        for (user_data, res) in completions {
+            if user_data == FSYNC_USER_DATA {
+                continue;
+            }
+            let index = user_data as usize;
+            let Some(offset) = self.in_flight[index].take() else {
+                continue;
+            };
+            let buf = &mut self.buffers[index];
+            if res < 0 {
+                result = result.and(Err(io::Error::from_raw_os_error(-res)));
+            } else if (res as usize) < buf.len() {
+                // 書ききれなかった残りは同期的に書く
+                let written = res as usize;
+                result = result.and(self.file.write_all_at(&buf[written..], offset + written as u64));
+            }
+            buf.clear();
+        }
+        result.and_then(|()| self.maybe_range_sync())
+    }
+
+    /// 先頭から書き込みが完了した範囲がbytes_per_syncを超えたら書き出しを始める
+    fn maybe_range_sync(&mut self) -> io::Result<()> {
+        if self.bytes_per_sync == 0 {
+            return Ok(());
+        }
+        let completed = self.in_flight.iter().flatten().copied().min().unwrap_or(self.offset);
+        if completed - self.synced_offset >= self.bytes_per_sync {
+            range_sync(&self.file, self.synced_offset, completed - self.synced_offset)?;
+            self.synced_offset = completed;
+        }
+        Ok(())
+    }
+
+    fn wait_all(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        loop {
+            let remaining = self.in_flight.iter().filter(|b| b.is_some()).count();
+            if remaining == 0 {
+                return result;
+            }
+            result = result.and(self.wait_one());
+            // submit自体が失敗し続ける場合は諦める
+            if self.in_flight.iter().filter(|b| b.is_some()).count() == remaining {
+                return result;
+            }
+        }
+    }
+}
+
+impl Write for UringFileWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let buf = &mut self.buffers[self.current];
+        let n = data.len().min(URING_BUFFER_SIZE - buf.len());
+        buf.extend_from_slice(&data[..n]);
+        if buf.len() == URING_BUFFER_SIZE {
+            self.queue_current()?;
+        }
+        Ok(n)
+    }
+
+    /// 書き込みは非同期に進むので、完了を待つのはfinish()
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WritableFile for UringFileWriter {
+    fn finish(&mut self) -> io::Result<()> {
+        self.queue_current()?;
+        self.wait_all()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.finish()?;
+        let entry = opcode::Fsync::new(types::Fd(self.file.as_raw_fd()))
+            .flags(types::FsyncFlags::DATASYNC)
+            .build()
+            .user_data(FSYNC_USER_DATA);
+        self.push(&entry)?;
+        self.submit_and_wait(1)?;
+
+        let res = self
+            .ring
+            .completion()
+            .find(|cqe| cqe.user_data() == FSYNC_USER_DATA)
+            .map(|cqe| cqe.result())
+            .ok_or_else(|| io::Error::other("missing fsync completion"))?;
+        if res < 0 {
+            return Err(io::Error::from_raw_os_error(-res));
+        }
+        self.synced_offset = self.offset;
+        Ok(())
+    }
+}
+
+impl Drop for UringFileWriter {
+    fn drop(&mut self) {
+        // カーネルが使っているバッファを解放しない
+        let _ = self.wait_all();
+        if self.in_flight.iter().any(Option::is_some) {
+            // 完了を回収できなかった書き込みのバッファは、カーネルが後から書き込むかもしれないので手放す
+            std::mem::forget(std::mem::take(&mut self.buffers));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uring_write() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("uring");
+        let Ok(mut writer) = UringFileWriter::new(File::create(&path).unwrap(), 64 * 1024) else {
+            // io_uringを使えない環境ではテストしない
+            return;
+        };
+
+        let data: Vec<u8> = (0..URING_BUFFER_SIZE * 7 + 123).map(|i| (i % 251) as u8).collect();
+        for chunk in data.chunks(5000) {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+        writer.sync().unwrap();
+        drop(writer);
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+    }
+}
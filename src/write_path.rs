@@ -1,9 +1,30 @@
-use std::fs::OpenOptions;
-use std::io::{Write, BufWriter};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
-use std::sync::mpsc::{sync_channel, SyncSender, Receiver};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::sync::mpsc::{sync_channel, SyncSender, Receiver, SendError, TrySendError};
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, Result};
+use crate::event_listener::{
+    EventListener, FlushJobInfo, MemTableInfo, WriteStallCondition, WriteStallInfo,
+};
+use crate::file_system::{default_file_system, FileLock, FileOptions, FileSystem};
+use crate::info_log::{InfoLog, StatsDumper};
+use crate::options::Options;
+use crate::perf_context::PerfTimer;
+use crate::rate_limiter::{IoPriority, RateLimitedFile};
+use crate::sstable::{BlockCache, TableBuilder, TableOptions, TableReader, TableReaderOptions};
+use crate::statistics::{Histogram, Ticker};
+use crate::table_cache::{table_file_path, TableCache};
+use crate::trace::{span, SpanId, NO_SPAN};
+
+/// data_dirを開いているWritePathが持つロックファイルの名前
+pub const LOCK_FILE_NAME: &str = "LOCK";
 
 /// ログエントリ
 #[derive(Clone)]
@@ -12,6 +33,9 @@ pub struct LogEntry {
     pub value: Vec<u8>,
 }
 
+/// フラッシュスレッドに送るimmutable MemTableと、それを作ったfreeze_memtableのspan
+type ImmutableMemTable = (MemTable, SpanId);
+
 /// Mutable なバッファ
 struct MemTable {
     entries: Vec<LogEntry>,
@@ -32,6 +56,13 @@ impl MemTable {
         self.size += entry_size;
     }
 
+    /// 最後の書き込みを取り消す（no_slowdownの書き込みを断るとき）
+    fn pop(&mut self) {
+        if let Some(entry) = self.entries.pop() {
+            self.size -= entry.key.len() + entry.value.len();
+        }
+    }
+
     fn size(&self) -> usize {
         self.size
     }
@@ -39,183 +70,1138 @@ impl MemTable {
     fn is_empty(&self) -> bool {
         self.entries.is_empty()
     }
+
+    /// キーの昇順に並べたエントリ（同じキーは最後に書き込んだものだけ）
+    ///
+    /// 書き込みは追記だけなので、並べ替えはフラッシュ時に行う。
+    /// ブロックのキーは厳密に昇順でなければならないため、上書きされた古い値はSSTableに書き出さない
+    /// （以前は同じキーのエントリをすべて書き出していたので、SSTableのエントリ数が変わる）
+    fn sorted_entries(&self) -> Vec<&LogEntry> {
+        let mut sorted: Vec<&LogEntry> = self.entries.iter().collect();
+        // 安定ソートなので同じキーは書き込み順のまま並ぶ
+        sorted.sort_by(|a, b| a.key.cmp(&b.key));
+        let mut deduped: Vec<&LogEntry> = Vec::with_capacity(sorted.len());
+        for entry in sorted {
+            match deduped.last_mut() {
+                Some(last) if last.key == entry.key => *last = entry,
+                _ => deduped.push(entry),
+            }
+        }
+        deduped
+    }
+}
+
+/// フラッシュのオプション（RocksDBのFlushOptions相当）
+#[derive(Clone, Debug)]
+pub struct FlushOptions {
+    /// immutable化したMemTableがすべて書き出され、fsyncされるまで待つ
+    pub wait: bool,
+    /// 待機後にデータディレクトリもfsyncする（新しいファイルのエントリを永続化する）
+    pub sync_dir: bool,
+}
+
+impl Default for FlushOptions {
+    fn default() -> Self {
+        Self {
+            wait: true,
+            sync_dir: false,
+        }
+    }
+}
+
+/// 書き込みのオプション（RocksDBのWriteOptions相当）
+#[derive(Clone, Debug, Default)]
+pub struct WriteOptions {
+    /// write stallで待つ代わりに`Error::WriteStall`を返す（書き込みは反映しない）
+    pub no_slowdown: bool,
+}
+
+/// close_with()のオプション
+#[derive(Clone, Debug, Default)]
+pub struct CloseOptions {
+    /// 未フラッシュのMemTableを書き出さずに、バックグラウンド処理を打ち切って終了する
+    ///
+    /// このクレートにはまだWALがないため、書き出されていない書き込みは失われる
+    pub cancel_background_work: bool,
+}
+
+/// WritePathの現在の状態（メトリクスのgauge）
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WritePathGauges {
+    /// mutable MemTableのサイズ（バイト）
+    pub memtable_size: u64,
+    /// フラッシュが完了していないimmutable MemTableの数（read-onlyモードで保持しているものを含む）
+    pub immutable_memtables: u64,
+    /// L0のSSTableの数
+    pub l0_files: u64,
+    /// コンパクションが必要なバイト数の見積もり（コンパクションはまだないので常に0）
+    pub pending_compaction_bytes: u64,
+    /// 書き込みがwrite stallで待たされている
+    pub write_stalled: bool,
+}
+
+/// バックグラウンドフラッシュの状態（書き込み側とフラッシュスレッドで共有）
+struct FlushState {
+    /// 直近のフラッシュ失敗（Someの間はread-onlyモード）
+    ///
+    /// `put`/`flush`のたびに`Error::BackgroundError`として共有して返す
+    bg_error: Option<Arc<Error>>,
+    /// 書き出せなかったimmutable MemTable（resume()で古い順に再試行する）
+    pending: VecDeque<MemTable>,
+    /// フラッシュスレッドに渡したMemTableの数
+    frozen: u64,
+    /// 書き出しが完了したMemTableの数（渡した順に完了する）
+    flushed: u64,
+    /// 書き出したがまだfsyncしていないSSTable
+    unsynced: Vec<PathBuf>,
+    /// renameしたSSTableのエントリをsync_dirできていない（flush_withでsync_dirする）
+    dir_unsynced: bool,
+    /// data_dirにあるSSTableの数（フラッシュの出力はすべてL0）
+    l0_files: u64,
+}
+
+/// ロックを解放してからリスナーに通知するイベント
+///
+/// コールバックがWritePathの内部のロックを待たせたり、ロックを取り直してデッドロックしたりしないよう、
+/// ロック中は`Shared::events`に積むだけにする
+enum Event {
+    MemTableSealed(MemTableInfo),
+    StallConditionsChanged(WriteStallInfo),
+    BackgroundError(Arc<Error>),
+}
+
+/// フラッシュスレッドと共有する状態
+struct Shared {
+    state: Mutex<FlushState>,
+    /// flushedが進んだ、またはbg_errorが設定されたことを通知する
+    flushed_cv: Condvar,
+    /// bg_errorの有無（putのたびにロックを取らないためのフラグ）
+    read_only: AtomicBool,
+    /// close_with()でバックグラウンド処理の打ち切りが要求された
+    cancelled: AtomicBool,
+    /// イベントを通知するリスナー（Options::listenersとLOGファイル）
+    listeners: Vec<Arc<dyn EventListener>>,
+    /// mutable MemTableのサイズ（メトリクスの取得でmemtableのロックを待たないため）
+    memtable_size: AtomicU64,
+    /// 書き込みがwrite stallで待たされている
+    write_stalled: AtomicBool,
+    /// 通知待ちのイベント（起きた順）
+    events: Mutex<VecDeque<Event>>,
+    /// イベントを通知しているスレッドが持つ（スレッドをまたいでも通知の順序を保つ）
+    notify_lock: Mutex<()>,
+}
+
+thread_local! {
+    /// このスレッドがリスナーに通知している最中のShared（コールバックからの再入を見分ける）
+    static NOTIFYING: Cell<*const Shared> = const { Cell::new(std::ptr::null()) };
+}
+
+impl Shared {
+    fn new(l0_files: u64, listeners: Vec<Arc<dyn EventListener>>) -> Self {
+        Self {
+            state: Mutex::new(FlushState {
+                bg_error: None,
+                pending: VecDeque::new(),
+                frozen: 0,
+                flushed: 0,
+                unsynced: Vec::new(),
+                dir_unsynced: false,
+                l0_files,
+            }),
+            flushed_cv: Condvar::new(),
+            read_only: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+            listeners,
+            memtable_size: AtomicU64::new(0),
+            write_stalled: AtomicBool::new(false),
+            events: Mutex::new(VecDeque::new()),
+            notify_lock: Mutex::new(()),
+        }
+    }
+
+    /// 状態をロックする
+    ///
+    /// 状態の更新は短い区間で完結しているので、ロックがpoisonされていても
+    /// そのまま中身を使う（致命的エラーの記録自体ができなくなるのを避ける）
+    fn lock_state(&self) -> MutexGuard<'_, FlushState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// read-onlyモードなら保存されているエラーを返す
+    fn check_bg_error(&self) -> Result<()> {
+        if !self.read_only.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        match &self.lock_state().bg_error {
+            Some(e) => Err(Self::stored_error(e)),
+            None => Ok(()),
+        }
+    }
+
+    /// 保存しているエラーを呼び出し側に返す形にする
+    fn stored_error(e: &Arc<Error>) -> Error {
+        match e.as_ref() {
+            Error::Fatal(msg) => Error::Fatal(msg.clone()),
+            _ => Error::BackgroundError(e.clone()),
+        }
+    }
+
+    /// フラッシュ失敗を記録してread-onlyモードに入る
+    ///
+    /// 失敗したMemTableは先頭に戻し、後続より先に再試行されるようにする
+    fn set_bg_error(&self, state: &mut FlushState, e: Arc<Error>, memtable: MemTable) {
+        state.pending.push_front(memtable);
+        self.set_error(state, e);
+    }
+
+    /// エラーを記録してread-onlyモードに入り、待機中のflush_and_waitを起こす
+    ///
+    /// 致命的エラーは通常のエラーで上書きしない。記録したときだけ通知する
+    fn set_error(&self, state: &mut FlushState, e: Arc<Error>) {
+        if !matches!(state.bg_error.as_deref(), Some(Error::Fatal(_))) {
+            state.bg_error = Some(e.clone());
+            self.push_event(Event::BackgroundError(e));
+        }
+        self.read_only.store(true, Ordering::Release);
+        self.flushed_cv.notify_all();
+    }
+
+    /// 致命的エラー（ロックのpoison、フラッシュスレッドの停止）を記録する
+    fn set_fatal(&self, state: &mut FlushState, message: String) {
+        self.set_error(state, Arc::new(Error::Fatal(message)));
+    }
+
+    /// イベントを通知待ちに積む（ロック中に呼んでよい）
+    fn push_event(&self, event: Event) {
+        self.events.lock().unwrap_or_else(PoisonError::into_inner).push_back(event);
+    }
+
+    /// 通知待ちのイベントを起きた順にリスナーに通知する
+    ///
+    /// memtableと状態のロックを解放してから呼ぶ。
+    /// コールバックの中から呼ばれた場合は、外側の呼び出しが続けて通知する
+    fn notify_listeners(&self) {
+        if std::ptr::eq(NOTIFYING.get(), self) {
+            return;
+        }
+        let _notify_lock = self.notify_lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let prev = NOTIFYING.replace(self);
+        // コールバックがパニックしても印を残さない
+        let _reset = ResetOnDrop(prev);
+        loop {
+            let Some(event) = self.events.lock().unwrap_or_else(PoisonError::into_inner).pop_front() else {
+                return;
+            };
+            for listener in &self.listeners {
+                match &event {
+                    Event::MemTableSealed(info) => listener.on_memtable_sealed(info),
+                    Event::StallConditionsChanged(info) => listener.on_stall_conditions_changed(info),
+                    Event::BackgroundError(e) => listener.on_background_error(e),
+                }
+            }
+        }
+    }
+
+    /// 書き出しが完了したSSTableを記録して待機中のflush_and_waitを起こす
+    ///
+    /// `synced`でなければflush_and_waitでfsyncする対象として覚えておく。
+    /// sync_dirに失敗していれば次のflush_withでsync_dirする
+    fn install(&self, state: &mut FlushState, output: FlushOutput, synced: bool) {
+        state.flushed += 1;
+        state.l0_files += 1;
+        if !synced {
+            state.unsynced.push(output.file_path);
+        }
+        state.dir_unsynced |= !output.dir_synced;
+        self.flushed_cv.notify_all();
+    }
+}
+
+/// dropでNOTIFYINGを元に戻す
+struct ResetOnDrop(*const Shared);
+
+impl Drop for ResetOnDrop {
+    fn drop(&mut self) {
+        NOTIFYING.set(self.0);
+    }
+}
+
+/// write_sstable()で書き出したSSTable
+struct FlushOutput {
+    file_path: PathBuf,
+    /// renameしたエントリを永続化した（sync_policy.sync_dirが無効なら常にtrue）
+    ///
+    /// rename後のsync_dirの失敗はエラーにしない。ファイルは設置済みなので、
+    /// MemTableを再試行すると同じデータが別の番号で二重に書き出されてしまう
+    dir_synced: bool,
+}
+
+/// フラッシュスレッドが使う共有データ（再起動時にも同じものを渡す）
+struct FlushContext {
+    fs: Arc<dyn FileSystem>,
+    data_dir: PathBuf,
+    counter: Arc<Mutex<usize>>,
+    shared: Arc<Shared>,
+    options: Options,
 }
 
 /// LSM-Tree の書き込みパス
 pub struct WritePath {
     /// 現在のmutableバッファ
     memtable: Arc<Mutex<MemTable>>,
-    /// バッファサイズの閾値
-    size_threshold: usize,
+    /// 設定（バッファサイズの閾値、immutable MemTableの最大数など）
+    options: Options,
     /// Immutableバッファを送信するチャネル (bounded channelでwrite stallを実現)
-    flush_sender: Option<SyncSender<MemTable>>,
+    ///
+    /// フラッシュスレッドを再起動するときに差し替える
+    flush_sender: Mutex<Option<SyncSender<ImmutableMemTable>>>,
     /// バックグラウンドスレッドのハンドル
-    flush_thread: Option<JoinHandle<()>>,
+    flush_thread: Mutex<Option<JoinHandle<()>>>,
     /// 出力ディレクトリ
     data_dir: PathBuf,
     /// SSTableファイルのカウンター
     sstable_counter: Arc<Mutex<usize>>,
-    /// immutable MemTableの最大数（RocksDBのmax_write_buffer_number相当）
-    max_write_buffer_number: usize,
+    /// バックグラウンドエラーと未フラッシュのMemTable
+    shared: Arc<Shared>,
+    /// resume()の同時実行を防ぐロック（再試行の順序を保つ）
+    resume_lock: Mutex<()>,
+    /// SSTableの読み込みで共有するブロックキャッシュ
+    block_cache: Option<Arc<BlockCache>>,
+    /// 開いたSSTableのキャッシュ
+    ///
+    /// SSTableを削除するときは`TableCache::evict`で取り除く（コンパクションはまだない）
+    table_cache: TableCache,
+    /// ファイル操作に使うファイルシステム
+    fs: Arc<dyn FileSystem>,
+    /// data_dirのLOGファイル
+    info_log: Arc<InfoLog>,
+    /// LOGに定期的に統計を書き出すスレッド（stats_dump_period_secが0ならNone）
+    stats_dumper: Option<StatsDumper>,
+    /// data_dirのロック（WritePathと一緒に破棄され、スレッドの終了後に解放される）
+    _lock: Box<dyn FileLock>,
 }
 
 impl WritePath {
-    /// 新しいWritePathを作成（デフォルトのmax_write_buffer_number = 2）
-    pub fn new<P: AsRef<Path>>(data_dir: P, size_threshold: usize) -> std::io::Result<Self> {
-        Self::with_max_write_buffers(data_dir, size_threshold, 2)
+    /// 設定を指定してWritePathを開く
+    ///
+    /// 設定はdata_dirのOPTIONSファイルに保存される。
+    /// 別のWritePath（他のプロセスを含む）が同じdata_dirを開いていれば`Error::Busy`を返す
+    pub fn open<P: AsRef<Path>>(data_dir: P, options: Options) -> Result<Self> {
+        Self::open_with_file_system(data_dir, options, default_file_system())
     }
 
-    /// max_write_buffer_numberを指定してWritePathを作成
-    pub fn with_max_write_buffers<P: AsRef<Path>>(
+    /// ファイルシステムを指定してWritePathを開く（テストではMemFileSystemを使う）
+    pub fn open_with_file_system<P: AsRef<Path>>(
         data_dir: P,
-        size_threshold: usize,
-        max_write_buffer_number: usize,
-    ) -> std::io::Result<Self> {
+        options: Options,
+        fs: Arc<dyn FileSystem>,
+    ) -> Result<Self> {
+        options.validate()?;
         let data_dir = data_dir.as_ref().to_path_buf();
+        let span = span!(INFO, "open", data_dir = %data_dir.display(), sstables = tracing::field::Empty);
 
         // データディレクトリを作成
-        std::fs::create_dir_all(&data_dir)?;
+        fs.create_dir_all(&data_dir)?;
+        // 同じdata_dirのSSTableを互いに上書きしないよう、閉じるまでLOCKファイルをロックする
+        let lock_path = data_dir.join(LOCK_FILE_NAME);
+        let lock = fs.lock_file(&lock_path).map_err(|e| match e.kind() {
+            io::ErrorKind::WouldBlock => Error::Busy(format!(
+                "{} is held by another WritePath",
+                lock_path.display()
+            )),
+            _ => Error::Io(e),
+        })?;
+        options.save_to_dir_with(&*fs, &data_dir)?;
 
-        // bounded channelで上限を設定（mutable 1個 + immutable (max-1)個）
-        // RocksDB: max_write_buffer_number個のMemTable（1 mutable + (max-1) immutable）
-        let buffer_capacity = max_write_buffer_number.saturating_sub(1).max(1);
-        let (tx, rx) = sync_channel(buffer_capacity);
-        let sstable_counter = Arc::new(Mutex::new(0));
+        // 既存のSSTableを上書きしないよう、続きの番号から採番する
+        let (next_file_number, l0_files) = Self::scan_sstables(&*fs, &data_dir)?;
+        span.record("sstables", l0_files);
+        let sstable_counter = Arc::new(Mutex::new(next_file_number));
+        let block_cache = (options.block_cache_size > 0)
+            .then(|| Arc::new(BlockCache::new(options.block_cache_size)));
+        // フラッシュで書き出したSSTableはすべてL0
+        let reader_options = TableReaderOptions {
+            file_system: Arc::clone(&fs),
+            block_cache: block_cache.clone(),
+            cache_index_blocks: options.cache_index_blocks,
+            pin_index_block: options.pin_l0_index_blocks,
+            use_mmap: options.allow_mmap_reads,
+            use_direct_reads: options.use_direct_reads,
+        };
+        let table_cache = TableCache::new(&data_dir, reader_options, options.max_open_files);
 
-        // バックグラウンドフラッシュスレッドを起動
-        let flush_thread = Self::spawn_flush_thread(rx, data_dir.clone(), sstable_counter.clone());
+        // LOGファイルもリスナーとしてフラッシュやwrite stallを記録する
+        let info_log = Arc::new(InfoLog::open(Arc::clone(&fs), &data_dir, &options));
+        info_log.log(format_args!(
+            "WritePath opened: {} ({} SSTables, next file #{})",
+            data_dir.display(),
+            l0_files,
+            next_file_number
+        ));
+        info_log.log_options(&options);
+        let mut listeners = options.listeners.clone();
+        listeners.push(info_log.clone());
+        let stats_dumper = (options.stats_dump_period_sec > 0).then(|| {
+            let period = Duration::from_secs(options.stats_dump_period_sec);
+            StatsDumper::start(info_log.clone(), period, options.statistics.clone())
+        });
 
-        Ok(Self {
+        let shared = Arc::new(Shared::new(l0_files, listeners));
+        let write_path = Self {
             memtable: Arc::new(Mutex::new(MemTable::new())),
-            size_threshold,
-            flush_sender: Some(tx),
-            flush_thread: Some(flush_thread),
+            options,
+            flush_sender: Mutex::new(None),
+            flush_thread: Mutex::new(None),
             data_dir,
             sstable_counter,
-            max_write_buffer_number,
+            shared,
+            resume_lock: Mutex::new(()),
+            block_cache,
+            table_cache,
+            fs,
+            info_log,
+            stats_dumper,
+            _lock: lock,
+        };
+
+        // バックグラウンドフラッシュスレッドを起動
+        write_path.start_flush_worker();
+        Ok(write_path)
+    }
+
+    /// ブロックキャッシュ（block_cache_sizeが0ならNone）
+    pub fn block_cache(&self) -> Option<&Arc<BlockCache>> {
+        self.block_cache.as_ref()
+    }
+
+    /// ファイル番号のSSTableを開く
+    ///
+    /// 開いたTableReaderはテーブルキャッシュに保持され、ブロックキャッシュを共有する
+    pub fn open_table(&self, file_number: u64) -> Result<Arc<TableReader>> {
+        self.table_cache.find_table(file_number)
+    }
+
+    /// 開いたSSTableのキャッシュ
+    ///
+    /// SSTableを削除するときは`TableCache::evict`で取り除く（コンパクションはまだない）
+    pub fn table_cache(&self) -> &TableCache {
+        &self.table_cache
+    }
+
+    /// チャネルを作り直してバックグラウンドフラッシュスレッドを起動する
+    fn start_flush_worker(&self) {
+        // bounded channelで上限を設定（mutable 1個 + immutable (max-1)個）
+        // RocksDB: max_write_buffer_number個のMemTable（1 mutable + (max-1) immutable）
+        let buffer_capacity = self.options.max_write_buffer_number.saturating_sub(1).max(1);
+        let (tx, rx) = sync_channel(buffer_capacity);
+
+        let ctx = FlushContext {
+            fs: Arc::clone(&self.fs),
+            data_dir: self.data_dir.clone(),
+            counter: self.sstable_counter.clone(),
+            shared: self.shared.clone(),
+            options: self.options.clone(),
+        };
+        let flush_thread = Self::spawn_flush_thread(rx, ctx);
+
+        *self.flush_sender.lock().unwrap_or_else(PoisonError::into_inner) = Some(tx);
+        *self.flush_thread.lock().unwrap_or_else(PoisonError::into_inner) = Some(flush_thread);
+    }
+
+    /// フラッシュスレッドが（パニックで）終了しているか
+    ///
+    /// SSTableの書き出し中のパニックはキャッチするので、終了するのは
+    /// それ以外（リスナーのコールバックなど）でパニックした場合だけ
+    fn flush_worker_is_dead(&self) -> bool {
+        let flush_thread = self.flush_thread.lock().unwrap_or_else(PoisonError::into_inner);
+        flush_thread.as_ref().is_none_or(|handle| handle.is_finished())
+    }
+
+    /// memtableをロックする
+    ///
+    /// 書き込み中のスレッドがパニックしてロックがpoisonされている場合、
+    /// MemTableの中身が壊れている可能性があるので致命的エラーにする
+    fn lock_memtable(&self) -> Result<MutexGuard<'_, MemTable>> {
+        self.memtable.lock().map_err(|_| {
+            let message = "memtable lock poisoned by a panicked writer".to_string();
+            self.shared.set_fatal(&mut self.shared.lock_state(), message.clone());
+            self.shared.notify_listeners();
+            Error::Fatal(message)
         })
     }
 
+    /// data_dirに保存されたOPTIONSファイルの設定でWritePathを開き直す
+    pub fn reopen<P: AsRef<Path>>(data_dir: P) -> Result<Self> {
+        Self::reopen_with_file_system(data_dir, default_file_system())
+    }
+
+    /// ファイルシステムを指定してWritePathを開き直す
+    pub fn reopen_with_file_system<P: AsRef<Path>>(data_dir: P, fs: Arc<dyn FileSystem>) -> Result<Self> {
+        let options = Options::load_from_dir_with(&*fs, data_dir.as_ref())?;
+        Self::open_with_file_system(data_dir, options, fs)
+    }
+
+    /// 現在の設定
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// 既存のSSTableの次のファイル番号とSSTableの数を求める
+    ///
+    /// 前回書きかけのまま残った一時ファイル（`.sst.tmp`）はここで削除する
+    fn scan_sstables(fs: &dyn FileSystem, data_dir: &Path) -> Result<(usize, u64)> {
+        let mut next = 0;
+        let mut count = 0;
+        for name in fs.list_dir(data_dir)? {
+            if name.ends_with(".sst.tmp") {
+                fs.remove_file(&data_dir.join(&name))?;
+            } else if let Some(num) = name.strip_suffix(".sst").and_then(|n| n.parse::<usize>().ok()) {
+                next = next.max(num + 1);
+                count += 1;
+            }
+        }
+        Ok((next, count))
+    }
+
+    /// メトリクスとして公開する現在の状態
+    pub fn gauges(&self) -> WritePathGauges {
+        let state = self.shared.lock_state();
+        WritePathGauges {
+            memtable_size: self.shared.memtable_size.load(Ordering::Relaxed),
+            immutable_memtables: state.frozen - state.flushed,
+            l0_files: state.l0_files,
+            pending_compaction_bytes: 0,
+            write_stalled: self.shared.write_stalled.load(Ordering::Relaxed),
+        }
+    }
+
     /// キーと値を書き込む
     ///
     /// immutable MemTableの数が上限に達している場合、
     /// フラッシュが完了するまで書き込みがブロックされる（write stall）
-    pub fn put(&self, key: Vec<u8>, value: Vec<u8>) -> std::io::Result<()> {
-        let mut memtable = self.memtable.lock().unwrap();
+    ///
+    /// バックグラウンドフラッシュが失敗している間（read-onlyモード）は
+    /// そのエラーを返し、書き込みを受け付けない。
+    /// ロックのpoisonやフラッシュスレッドの停止は`Error::Fatal`になる
+    ///
+    /// `WriteOptions::default()`でのput_with()と同じ
+    pub fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.put_with(key, value, &WriteOptions::default())
+    }
+
+    /// オプションを指定してキーと値を書き込む
+    ///
+    /// `no_slowdown`のときは、write stallになる書き込みを反映せずに`Error::WriteStall`を返す
+    pub fn put_with(&self, key: Vec<u8>, value: Vec<u8>, write_options: &WriteOptions) -> Result<()> {
+        let _span = span!(DEBUG, "put", key_bytes = key.len(), value_bytes = value.len());
+        let start = Instant::now();
+        self.shared.check_bg_error()?;
+        let timer = PerfTimer::start();
+        let mut memtable = self.lock_memtable()?;
+        timer.stop(|ctx| &mut ctx.write_mutex_wait_nanos);
 
+        let bytes = (key.len() + value.len()) as u64;
+        let timer = PerfTimer::start();
         memtable.put(key, value);
+        timer.stop(|ctx| &mut ctx.write_memtable_nanos);
+        self.shared.memtable_size.store(memtable.size() as u64, Ordering::Relaxed);
 
         // サイズ閾値を超えたらフラッシュ
         // このsend()でブロックする可能性がある（write stall）
-        if memtable.size() >= self.size_threshold {
-            self.freeze_memtable(&mut memtable)?;
+        if memtable.size() >= self.options.write_buffer_size {
+            let result = self.freeze_memtable(&mut memtable, write_options.no_slowdown);
+            if let Err(Error::WriteStall) = result {
+                memtable.pop();
+                self.shared.memtable_size.store(memtable.size() as u64, Ordering::Relaxed);
+            }
+            drop(memtable);
+            self.shared.notify_listeners();
+            result?;
         }
 
+        if let Some(stats) = &self.options.statistics {
+            stats.record_tick(Ticker::BytesWritten, bytes);
+            stats.record_tick(Ticker::KeysWritten, 1);
+            stats.record_micros(Histogram::PutMicros, start.elapsed());
+        }
         Ok(())
     }
 
     /// 現在のmemtableをimmutable化して新しいmemtableを作成
-    fn freeze_memtable(&self, memtable: &mut std::sync::MutexGuard<MemTable>) -> std::io::Result<()> {
+    ///
+    /// フラッシュスレッドがパニックで終了していた場合、MemTableはpendingに保持して
+    /// 致命的エラーを返す。シャットダウン後はMemTableをそのまま残して`Error::ShutdownInProgress`を返す。
+    /// `no_slowdown`ならwrite stallで待たずに、MemTableを戻して`Error::WriteStall`を返す。
+    /// リスナーへの通知は呼び出し側がmemtableのロックを解放してから行う
+    fn freeze_memtable(&self, memtable: &mut MutexGuard<MemTable>, no_slowdown: bool) -> Result<()> {
+        let span = span!(
+            INFO,
+            "freeze_memtable",
+            memtable_bytes = memtable.size(),
+            entries = memtable.entries.len(),
+            stall_micros = tracing::field::Empty
+        );
+        if memtable.is_empty() {
+            return Ok(());
+        }
+        // 送信先がなければ、データを失わないようにmemtableを取り出す前に断る
+        let flush_sender = self.flush_sender.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(sender) = flush_sender.as_ref() else {
+            return Err(Error::ShutdownInProgress);
+        };
+
         // 古いmemtableを取り出し、新しいmemtableと交換
         let old_memtable = std::mem::replace(&mut **memtable, MemTable::new());
+        self.shared.memtable_size.store(0, Ordering::Relaxed);
 
         // バックグラウンドスレッドに送信
-        if !old_memtable.is_empty() {
-            if let Some(sender) = &self.flush_sender {
-                sender.send(old_memtable)
-                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+        let info = MemTableInfo {
+            num_entries: old_memtable.entries.len() as u64,
+            data_size: old_memtable.size() as u64,
+        };
+
+        // 送信してからsealedを積むまでstateのロックを持ち、
+        // フラッシュスレッドがon_flush_beginを先に通知しないようにする
+        let mut state = self.shared.lock_state();
+        let result = match sender.try_send((old_memtable, span.id())) {
+            Ok(()) => {
+                self.shared.push_event(Event::MemTableSealed(info));
+                Ok(())
             }
+            Err(TrySendError::Full((old_memtable, _))) if no_slowdown => {
+                // 待たずに断る。MemTableは戻すので、次の書き込みでまたフラッシュを試す
+                **memtable = old_memtable;
+                self.shared.memtable_size.store(memtable.size() as u64, Ordering::Relaxed);
+                return Err(Error::WriteStall);
+            }
+            Err(TrySendError::Full(old_memtable)) => {
+                self.shared.push_event(Event::MemTableSealed(info));
+                drop(state);
+                // immutable MemTableが上限に達しているので、フラッシュを待つ（write stall）
+                let start = Instant::now();
+                let timer = PerfTimer::start();
+                self.set_write_stalled(true);
+                let result = sender.send(old_memtable).map_err(|SendError((memtable, _))| memtable);
+                self.set_write_stalled(false);
+                timer.stop(|ctx| &mut ctx.write_stall_nanos);
+                let elapsed = start.elapsed();
+                span.record("stall_micros", elapsed.as_micros() as u64);
+                if let Some(stats) = &self.options.statistics {
+                    stats.record_tick(Ticker::StallMicros, elapsed.as_micros() as u64);
+                    stats.record_micros(Histogram::StallMicros, elapsed);
+                }
+                state = self.shared.lock_state();
+                result
+            }
+            Err(TrySendError::Disconnected((old_memtable, _))) => {
+                self.shared.push_event(Event::MemTableSealed(info));
+                Err(old_memtable)
+            }
+        };
+
+        // memtableのロック中に数えるので、送信順とfrozenの順序が一致する
+        state.frozen += 1;
+        if let Err(old_memtable) = result {
+            // 受信側がいない = フラッシュスレッドが異常終了している
+            state.pending.push_back(old_memtable);
+            self.shared.set_fatal(&mut state, "flush worker is not running".to_string());
+            return Err(Shared::stored_error(state.bg_error.as_ref().unwrap()));
         }
 
         Ok(())
     }
 
+    /// write stallの状態を更新して通知待ちに積む
+    ///
+    /// 待たされるのはmemtableのロックを持った1つの書き込みだけなので、通知の順序は入れ替わらない。
+    /// Stoppedは止まっている間にフラッシュスレッドが代わりに通知することがある
+    fn set_write_stalled(&self, stalled: bool) {
+        self.shared.write_stalled.store(stalled, Ordering::Relaxed);
+        let (prev, cur) = match stalled {
+            true => (WriteStallCondition::Normal, WriteStallCondition::Stopped),
+            false => (WriteStallCondition::Stopped, WriteStallCondition::Normal),
+        };
+        self.shared.push_event(Event::StallConditionsChanged(WriteStallInfo { prev, cur }));
+    }
+
     /// 明示的にフラッシュ（すべてのデータをディスクに書き出す）
-    pub fn flush(&self) -> std::io::Result<()> {
-        let mut memtable = self.memtable.lock().unwrap();
-        if !memtable.is_empty() {
-            self.freeze_memtable(&mut memtable)?;
+    ///
+    /// read-onlyモード中はバックグラウンドエラーを返す
+    pub fn flush(&self) -> Result<()> {
+        self.shared.check_bg_error()?;
+        let mut memtable = self.lock_memtable()?;
+        if memtable.is_empty() {
+            return Ok(());
+        }
+        let result = self.freeze_memtable(&mut memtable, false);
+        drop(memtable);
+        self.shared.notify_listeners();
+        result
+    }
+
+    /// フラッシュしてディスクへの永続化を待つ
+    ///
+    /// `FlushOptions::default()`でのflush_with()と同じ
+    pub fn flush_and_wait(&self) -> Result<()> {
+        self.flush_with(&FlushOptions::default())
+    }
+
+    /// オプションを指定してフラッシュ
+    ///
+    /// `wait`が有効な場合、この呼び出しまでにimmutable化したすべてのMemTableが
+    /// SSTableとして書き出され、fsyncされるまで戻らない。
+    /// 途中でバックグラウンドエラーが発生した場合はそのエラーを返す
+    pub fn flush_with(&self, options: &FlushOptions) -> Result<()> {
+        self.flush()?;
+        if !options.wait {
+            return Ok(());
+        }
+
+        let unsynced = {
+            let mut state = self.shared.lock_state();
+            let target = state.frozen;
+            while state.flushed < target {
+                if let Some(e) = &state.bg_error {
+                    return Err(Shared::stored_error(e));
+                }
+                state = self
+                    .shared
+                    .flushed_cv
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+            std::mem::take(&mut state.unsynced)
+        };
+
+        for (i, file_path) in unsynced.iter().enumerate() {
+            if let Err(e) = self.fs.sync_file(file_path) {
+                // fsyncできなかったファイルは次回の呼び出しで再試行する
+                self.shared.lock_state().unsynced.extend_from_slice(&unsynced[i..]);
+                return Err(e.into());
+            }
+        }
+
+        // フラッシュスレッドでsync_dirに失敗していれば、ここでやり直す
+        let dir_unsynced = std::mem::take(&mut self.shared.lock_state().dir_unsynced);
+        if options.sync_dir || dir_unsynced {
+            if let Err(e) = self.fs.sync_dir(&self.data_dir) {
+                self.shared.lock_state().dir_unsynced |= dir_unsynced;
+                return Err(e.into());
+            }
         }
         Ok(())
     }
 
+    /// 書き込みパスを閉じる
+    ///
+    /// 残りのMemTableをフラッシュしてfsyncし、バックグラウンドスレッドの終了を待ってから
+    /// データディレクトリをfsyncする。途中で発生したエラーを返す。
+    /// read-onlyモードのまま閉じた場合は、保持していたMemTableを破棄してバックグラウンドエラーを返す
+    /// （dropではfsyncせず、エラーも無視される）
+    pub fn close(self) -> Result<()> {
+        self.close_with(&CloseOptions::default())
+    }
+
+    /// オプションを指定して書き込みパスを閉じる
+    pub fn close_with(mut self, options: &CloseOptions) -> Result<()> {
+        self.shutdown(options, true)
+    }
+
+    /// 終了処理（close_with()とdropで共通）
+    ///
+    /// `durable`ならフラッシュしたSSTableとデータディレクトリをfsyncする。
+    /// 最初に発生したエラーを返すが、スレッドの終了待ちは必ず行う
+    fn shutdown(&mut self, options: &CloseOptions, durable: bool) -> Result<()> {
+        // 2回目以降（close後のdrop）は何もしない
+        if self.flush_thread.get_mut().unwrap_or_else(PoisonError::into_inner).is_none() {
+            return Ok(());
+        }
+
+        let mut result = if options.cancel_background_work {
+            self.shared.cancelled.store(true, Ordering::Release);
+            Ok(())
+        } else if durable {
+            // 残りのデータをフラッシュして永続化を待つ
+            self.flush_with(&FlushOptions { wait: true, sync_dir: false })
+        } else {
+            // 書き出しはスレッドの終了待ちで完了する（fsyncはsync_policyに従う）
+            self.flush()
+        };
+
+        // flush_senderをdropしてチャネルを閉じる
+        // これによりバックグラウンドスレッドのrecv()が終了する
+        let flush_sender = self.flush_sender.get_mut().unwrap_or_else(PoisonError::into_inner);
+        drop(flush_sender.take());
+
+        // バックグラウンドスレッドが終了するまで待機
+        let flush_thread = self.flush_thread.get_mut().unwrap_or_else(PoisonError::into_inner);
+        if let Some(thread) = flush_thread.take() {
+            if thread.join().is_err() && result.is_ok() {
+                result = Err(Error::Fatal("flush worker panicked".to_string()));
+            }
+        }
+
+        // 作成したSSTableのディレクトリエントリを永続化する
+        if result.is_ok() && durable && !options.cancel_background_work {
+            result = self.fs.sync_dir(&self.data_dir).map_err(Error::from);
+        }
+
+        // read-onlyモードで書き出せなかったMemTableは失われる
+        let memtable_is_empty = self.memtable.lock().unwrap_or_else(PoisonError::into_inner).is_empty();
+        // LOGへの書き込みは状態のロックを解放してから行う
+        let (bg_error, unflushed) = {
+            let state = self.shared.lock_state();
+            (state.bg_error.clone(), state.pending.len() + usize::from(!memtable_is_empty))
+        };
+        if let Some(e) = &bg_error {
+            self.info_log.log(format_args!(
+                "[error] closing in read-only mode, discarding {} unflushed memtables: {}",
+                unflushed, e
+            ));
+            if result.is_ok() {
+                result = Err(Shared::stored_error(e));
+            }
+        }
+
+        drop(self.stats_dumper.take());
+        match &result {
+            Ok(()) => self.info_log.log(format_args!("WritePath closed")),
+            Err(e) => self.info_log.log(format_args!("WritePath closed with error: {}", e)),
+        }
+        result
+    }
+
+    /// バックグラウンドエラーから復帰する
+    ///
+    /// 失敗したフラッシュの原因（ディスクフルなど）を取り除いた後に呼び出す。
+    /// 保持していたMemTableを古い順に書き出し、すべて成功したら
+    /// read-onlyモードを解除する。再び失敗した場合はそのエラーを返し、
+    /// read-onlyモードのままになる。
+    /// 別のスレッドがresume中の場合は`Error::Busy`を返す
+    ///
+    /// フラッシュスレッドがパニックで終了している場合、
+    /// `Options::restart_flush_worker`が有効なら再起動してから再試行する。
+    /// 無効な場合やmemtableのロックがpoisonされている場合は致命的エラーを返す
+    pub fn resume(&self) -> Result<()> {
+        let span = span!(INFO, "resume", restarted_flush_worker = false);
+        let _guard = match self.resume_lock.try_lock() {
+            Ok(guard) => guard,
+            Err(std::sync::TryLockError::WouldBlock) => {
+                return Err(Error::Busy("resume is already in progress".to_string()));
+            }
+            Err(std::sync::TryLockError::Poisoned(e)) => e.into_inner(),
+        };
+
+        if self.memtable.is_poisoned() {
+            return Err(Error::Fatal("memtable lock poisoned by a panicked writer".to_string()));
+        }
+        if self.flush_worker_is_dead() {
+            if !self.options.restart_flush_worker {
+                return Err(Error::Fatal("flush worker is not running".to_string()));
+            }
+            if let Some(handle) = self.flush_thread.lock().unwrap_or_else(PoisonError::into_inner).take() {
+                let _ = handle.join();
+            }
+            self.start_flush_worker();
+            span.record("restarted_flush_worker", true);
+        }
+
+        loop {
+            // bg_errorを残したまま1つずつ取り出す
+            // （その間に届いたMemTableはフラッシュスレッドがpendingの末尾に積む）
+            let memtable = {
+                let mut state = self.shared.lock_state();
+                match state.pending.pop_front() {
+                    Some(memtable) => memtable,
+                    None => {
+                        // resume()の開始時点で原因を取り除いてあるので、致命的エラーも解除する
+                        let resumed = state.bg_error.take().is_some();
+                        self.shared.read_only.store(false, Ordering::Release);
+                        drop(state);
+                        if resumed {
+                            self.info_log.log(format_args!("[error] resumed from background error"));
+                        }
+                        return Ok(());
+                    }
+                }
+            };
+
+            self.shared.notify_listeners();
+            let result = Self::write_sstable_catching_panic(
+                &*self.fs,
+                &self.data_dir,
+                &memtable,
+                &self.sstable_counter,
+                &self.options,
+                &self.shared.listeners,
+                NO_SPAN,
+            );
+            match result {
+                Ok(output) => {
+                    let mut state = self.shared.lock_state();
+                    self.shared.install(&mut state, output, self.options.sync_policy.sync_sstables);
+                }
+                Err(e) => {
+                    let e = Arc::new(e);
+                    self.shared.set_bg_error(&mut self.shared.lock_state(), e.clone(), memtable);
+                    self.shared.notify_listeners();
+                    return Err(Error::BackgroundError(e));
+                }
+            }
+        }
+    }
+
     /// バックグラウンドフラッシュスレッドを生成
-    fn spawn_flush_thread(
-        rx: Receiver<MemTable>,
-        data_dir: PathBuf,
-        counter: Arc<Mutex<usize>>,
-    ) -> JoinHandle<()> {
+    ///
+    /// SSTableの書き出し中のパニックは致命的エラーとして記録し、
+    /// MemTableはresume()での再試行のために保持する
+    fn spawn_flush_thread(rx: Receiver<ImmutableMemTable>, ctx: FlushContext) -> JoinHandle<()> {
         thread::spawn(move || {
-            while let Ok(memtable) = rx.recv() {
-                if let Err(e) = Self::write_sstable(&data_dir, &memtable, &counter) {
-                    eprintln!("Failed to write SSTable: {}", e);
+            let shared = ctx.shared.clone();
+            while let Ok((memtable, freeze_span)) = rx.recv() {
+                // 打ち切りが要求されていれば、残りは書き出さずに捨てる
+                if shared.cancelled.load(Ordering::Acquire) {
+                    continue;
+                }
+
+                // read-onlyモード中（または再試行待ちがある間）は書き出さずに保持する
+                // 古いMemTableより先に新しいものが書き出されないようにするため
+                {
+                    let mut state = shared.lock_state();
+                    if state.bg_error.is_some() || !state.pending.is_empty() {
+                        state.pending.push_back(memtable);
+                        continue;
+                    }
+                }
+
+                // on_memtable_sealedなど先に起きたイベントをon_flush_beginより先に通知する
+                shared.notify_listeners();
+                let result = Self::write_sstable_catching_panic(
+                    &*ctx.fs,
+                    &ctx.data_dir,
+                    &memtable,
+                    &ctx.counter,
+                    &ctx.options,
+                    &shared.listeners,
+                    freeze_span,
+                );
+                let mut state = shared.lock_state();
+                match result {
+                    Ok(output) => shared.install(&mut state, output, ctx.options.sync_policy.sync_sstables),
+                    Err(e) => shared.set_bg_error(&mut state, Arc::new(e), memtable),
                 }
+                drop(state);
+                shared.notify_listeners();
             }
         })
     }
 
+    /// write_sstable()のパニックを致命的エラーにする
+    ///
+    /// ファイルシステムやリスナーのパニックでフラッシュスレッドごと止まらないようにする
+    fn write_sstable_catching_panic(
+        fs: &dyn FileSystem,
+        data_dir: &Path,
+        memtable: &MemTable,
+        counter: &Arc<Mutex<usize>>,
+        options: &Options,
+        listeners: &[Arc<dyn EventListener>],
+        freeze_span: SpanId,
+    ) -> Result<FlushOutput> {
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            Self::write_sstable(fs, data_dir, memtable, counter, options, listeners, freeze_span)
+        }))
+        .unwrap_or_else(|payload| {
+            Err(Error::Fatal(format!("flush worker panicked: {}", panic_message(&*payload))))
+        })
+    }
+
     /// SSTableファイルに書き出す
+    ///
+    /// 一時ファイル（`.sst.tmp`）に書き出してからrenameするので、
+    /// 書きかけのSSTableが`.sst`として見えることはない。
+    /// rename前に失敗した場合は一時ファイルを削除してエラーを返す。
+    /// flushのspanはMemTableをimmutableにしたfreeze_memtableのspanの後に続くものとして記録する
     fn write_sstable(
+        fs: &dyn FileSystem,
         data_dir: &Path,
         memtable: &MemTable,
         counter: &Arc<Mutex<usize>>,
-    ) -> std::io::Result<()> {
+        options: &Options,
+        listeners: &[Arc<dyn EventListener>],
+        freeze_span: SpanId,
+    ) -> Result<FlushOutput> {
         let file_num = {
-            let mut c = counter.lock().unwrap();
+            let mut c = counter.lock().unwrap_or_else(PoisonError::into_inner);
             let num = *c;
             *c += 1;
             num
         };
 
-        let file_path = data_dir.join(format!("{:06}.sst", file_num));
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(file_path)?;
-
-        // BufWriterでバッファリング（デフォルト8KB）
-        let mut writer = BufWriter::new(file);
+        let span = span!(
+            INFO,
+            "flush",
+            file_number = file_num,
+            memtable_bytes = memtable.size(),
+            file_size = tracing::field::Empty,
+            num_entries = tracing::field::Empty
+        );
+        span.follows_from(freeze_span);
+        let start = Instant::now();
+        let file_path = table_file_path(data_dir, file_num as u64);
+        let tmp_path = data_dir.join(format!("{:06}.sst.tmp", file_num));
+        let mut info = FlushJobInfo {
+            file_path: file_path.clone(),
+            file_number: file_num as u64,
+            file_size: 0,
+            num_entries: 0,
+            duration: Duration::ZERO,
+        };
+        for listener in listeners {
+            listener.on_flush_begin(&info);
+        }
 
-        // シンプルなフォーマット: [key_len: u32][key][value_len: u32][value]
-        for entry in &memtable.entries {
-            writer.write_all(&(entry.key.len() as u32).to_le_bytes())?;
-            writer.write_all(&entry.key)?;
-            writer.write_all(&(entry.value.len() as u32).to_le_bytes())?;
-            writer.write_all(&entry.value)?;
+        let entries = memtable.sorted_entries();
+        let file_size = match Self::write_entries(fs, &tmp_path, &entries, options) {
+            Ok(file_size) => file_size,
+            Err(e) => {
+                // 書きかけのファイルは残さない（MemTableは再試行のために保持されている）
+                let _ = fs.remove_file(&tmp_path);
+                return Err(e);
+            }
+        };
+        if let Err(e) = fs.rename(&tmp_path, &file_path) {
+            let _ = fs.remove_file(&tmp_path);
+            return Err(e.into());
         }
 
-        // flushでバッファをディスクに書き出す（fsyncはしない）
-        writer.flush()?;
-        Ok(())
+        // renameしたエントリを永続化する（失敗してもSSTableは設置済み）
+        let dir_synced = !options.sync_policy.sync_dir || fs.sync_dir(data_dir).is_ok();
+
+        if let Some(stats) = &options.statistics {
+            stats.record_tick(Ticker::Flushes, 1);
+            stats.record_tick(Ticker::FlushBytesWritten, file_size);
+            stats.record_micros(Histogram::FlushMicros, start.elapsed());
+            stats.record_in_histogram(Histogram::SstableSize, file_size);
+        }
+        span.record("file_size", file_size);
+        span.record("num_entries", entries.len());
+        info.file_size = file_size;
+        info.num_entries = entries.len() as u64;
+        info.duration = start.elapsed();
+        for listener in listeners {
+            listener.on_flush_completed(&info);
+        }
+        Ok(FlushOutput { file_path, dir_synced })
     }
-}
 
-impl Drop for WritePath {
-    fn drop(&mut self) {
-        // 残りのデータをフラッシュ（エラーは無視）
-        let _ = self.flush();
+    /// キーの順に並べたエントリをブロック形式のSSTableとして書き出し、ファイルのサイズを返す
+    ///
+    /// フラッシュの出力はL0なので、L0の圧縮方式を使う
+    fn write_entries(
+        fs: &dyn FileSystem,
+        file_path: &Path,
+        entries: &[&LogEntry],
+        options: &Options,
+    ) -> Result<u64> {
+        let file_options = FileOptions {
+            use_direct_reads: false,
+            use_direct_writes: options.use_direct_io_for_flush_and_compaction,
+            use_io_uring: options.use_io_uring,
+            bytes_per_sync: options.sync_policy.bytes_per_sync,
+        };
+        let mut writer = fs.new_writable_file(file_path, &file_options)?;
+        if let Some(limiter) = &options.rate_limiter {
+            // フラッシュはコンパクションより優先する
+            writer = Box::new(RateLimitedFile::new(writer, Arc::clone(limiter), IoPriority::High));
+        }
+        let (mut writer, file_size) = Self::build_table(writer, entries, options)?;
 
-        // flush_senderをdropしてチャネルを閉じる
-        // これによりバックグラウンドスレッドのrecv()が終了する
-        drop(self.flush_sender.take());
+        // バッファや書き込み中のデータを書き出す（fsyncはsync_policyに従う）
+        writer.finish()?;
+        if options.sync_policy.sync_sstables {
+            writer.sync()?;
+        }
+        Ok(file_size)
+    }
 
-        // バックグラウンドスレッドが終了するまで待機
-        if let Some(thread) = self.flush_thread.take() {
-            let _ = thread.join();
+    /// エントリを順にTableBuilderで書き出す
+    fn build_table<W: Write>(writer: W, entries: &[&LogEntry], options: &Options) -> Result<(W, u64)> {
+        let table_options = TableOptions {
+            block_size: options.block_size,
+            block_restart_interval: options.block_restart_interval,
+            compression: options.compression_for_level(0, false),
+            min_compression_ratio: options.min_compression_ratio,
+            zstd_max_dict_bytes: options.zstd_max_dict_bytes,
+            zstd_max_train_bytes: options.zstd_max_train_bytes,
+        };
+        let mut builder = TableBuilder::new(writer, table_options);
+        for entry in entries {
+            builder.add(&entry.key, &entry.value)?;
         }
+        builder.finish()
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-
+/// パニックのペイロードからメッセージを取り出す
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+impl Drop for WritePath {
+    fn drop(&mut self) {
+        // close()していなければ、残りをフラッシュしてスレッドの終了を待つ（エラーは無視）
+        // スループット優先でfsyncしない。永続化が必要ならclose()を使う
+        let _ = self.shutdown(&CloseOptions::default(), false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::SyncPolicy;
+    use crate::sstable::{CompressionType, TableReader};
+    use std::fs;
+
+    fn test_options(write_buffer_size: usize) -> Options {
+        Options::builder()
+            .write_buffer_size(write_buffer_size)
+            .build()
+            .unwrap()
+    }
+
+    /// data_dirのすべてのSSTableのエントリをファイル番号順に読む
+    fn read_entries(data_dir: &Path) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(data_dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "sst"))
+            .collect();
+        paths.sort();
+        paths
+            .iter()
+            .flat_map(|path| {
+                let reader = TableReader::open(path).unwrap();
+                reader.iter().collect::<Result<Vec<_>>>().unwrap()
+            })
+            .collect()
+    }
+
+    fn contains_value(entries: &[(Vec<u8>, Vec<u8>)], value: &[u8]) -> bool {
+        entries.iter().any(|(_, v)| v == value)
+    }
+
     #[test]
     fn test_put_and_flush() {
         let temp_dir = tempfile::tempdir().unwrap();
-        let write_path = WritePath::new(temp_dir.path(), 1024).unwrap();
+        let write_path = WritePath::open(temp_dir.path(), test_options(1024)).unwrap();
 
         // データを書き込む
         write_path.put(b"key1".to_vec(), b"value1".to_vec()).unwrap();
@@ -231,7 +1217,7 @@ mod tests {
         let files: Vec<_> = fs::read_dir(temp_dir.path())
             .unwrap()
             .filter_map(|e| e.ok())
-            .filter(|e| e.path().extension().map_or(false, |ext| ext == "sst"))
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "sst"))
             .collect();
 
         assert!(!files.is_empty(), "SSTable file should be created");
@@ -240,7 +1226,7 @@ mod tests {
     #[test]
     fn test_automatic_flush_on_size_threshold() {
         let temp_dir = tempfile::tempdir().unwrap();
-        let write_path = WritePath::new(temp_dir.path(), 100).unwrap(); // 小さい閾値
+        let write_path = WritePath::open(temp_dir.path(), test_options(100)).unwrap(); // 小さい閾値
 
         // 閾値を超えるデータを書き込む
         for i in 0..10 {
@@ -256,7 +1242,7 @@ mod tests {
         let files: Vec<_> = fs::read_dir(temp_dir.path())
             .unwrap()
             .filter_map(|e| e.ok())
-            .filter(|e| e.path().extension().map_or(false, |ext| ext == "sst"))
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "sst"))
             .collect();
 
         assert!(files.len() > 1, "Multiple SSTable files should be created");
@@ -271,9 +1257,12 @@ mod tests {
 
         // max_write_buffer_number=2, 小さいsize_thresholdでテスト
         // これにより1個のimmutable MemTableまでしか保持できない
-        let write_path = Arc::new(
-            WritePath::with_max_write_buffers(temp_dir.path(), 100, 2).unwrap()
-        );
+        let options = Options::builder()
+            .write_buffer_size(100)
+            .max_write_buffer_number(2)
+            .build()
+            .unwrap();
+        let write_path = Arc::new(WritePath::open(temp_dir.path(), options).unwrap());
 
         // フラッシュスレッドを遅延させるため、大量の小さいフラッシュを発生させる
         let stalled = Arc::new(AtomicBool::new(false));
@@ -306,4 +1295,1086 @@ mod tests {
         // （フラッシュが追いつかず、send()がブロックされたはず）
         println!("Write stall detected: {}", stalled.load(Ordering::SeqCst));
     }
+
+    #[test]
+    fn test_background_error_enters_read_only_and_resume() {
+        use std::time::{Duration, Instant};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let data_dir = temp_dir.path().join("db");
+        let write_path = WritePath::open(&data_dir, test_options(1024)).unwrap();
+
+        // ディレクトリを消してSSTableの作成を失敗させる
+        fs::remove_dir_all(&data_dir).unwrap();
+        write_path.put(b"key1".to_vec(), b"value1".to_vec()).unwrap();
+        write_path.flush().unwrap();
+
+        // フラッシュスレッドが失敗するとread-onlyモードになる
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let err = loop {
+            match write_path.put(b"key2".to_vec(), b"value2".to_vec()) {
+                Err(e) => break e,
+                Ok(()) => {
+                    assert!(Instant::now() < deadline, "background error was not reported");
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+        };
+        // 元のエラーはsource()でたどれる
+        match &err {
+            Error::BackgroundError(cause) => match cause.as_ref() {
+                Error::Io(e) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+                other => panic!("unexpected cause: {:?}", other),
+            },
+            other => panic!("unexpected error: {:?}", other),
+        }
+        assert!(std::error::Error::source(&err).is_some());
+        assert!(matches!(write_path.flush(), Err(Error::BackgroundError(_))));
+
+        // 原因が残っている間はresumeも失敗する
+        assert!(write_path.resume().is_err());
+        assert!(write_path.put(b"key3".to_vec(), b"value3".to_vec()).is_err());
+
+        // 原因を取り除けば保持していたMemTableが書き出され、書き込みを再開できる
+        fs::create_dir_all(&data_dir).unwrap();
+        write_path.resume().unwrap();
+        write_path.put(b"key4".to_vec(), b"value4".to_vec()).unwrap();
+        drop(write_path);
+
+        let entries = read_entries(&data_dir);
+        assert!(contains_value(&entries, b"value1"), "memtable kept for retry should be flushed");
+        assert!(contains_value(&entries, b"value4"));
+    }
+
+    #[test]
+    fn test_flush_and_wait() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let write_path = WritePath::open(temp_dir.path(), test_options(100)).unwrap();
+
+        for i in 0..10 {
+            let key = format!("key{:03}", i).into_bytes();
+            write_path.put(key, vec![b'x'; 50]).unwrap();
+        }
+        write_path.put(b"last".to_vec(), b"last_value".to_vec()).unwrap();
+
+        write_path
+            .flush_with(&FlushOptions { wait: true, sync_dir: true })
+            .unwrap();
+
+        // dropを待たずに、すべてのデータがSSTableに書き出されている
+        let entries = read_entries(temp_dir.path());
+        assert_eq!(entries.len(), 11);
+        assert_eq!(entries.last().unwrap(), &(b"last".to_vec(), b"last_value".to_vec()));
+
+        // 何も書いていなければすぐに戻る
+        write_path.flush_and_wait().unwrap();
+    }
+
+    #[test]
+    fn test_flush_sorts_and_keeps_latest_value() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let write_path = WritePath::open(temp_dir.path(), test_options(1024 * 1024)).unwrap();
+
+        for i in [3u64, 1, 2, 1] {
+            write_path
+                .put(format!("{:016}", i).into_bytes(), format!("value{}", i).into_bytes())
+                .unwrap();
+        }
+        write_path.put(format!("{:016}", 1).into_bytes(), b"latest".to_vec()).unwrap();
+        write_path.flush_and_wait().unwrap();
+
+        let entries = read_entries(temp_dir.path());
+        let keys: Vec<_> = entries.iter().map(|(key, _)| key.clone()).collect();
+        assert_eq!(keys, [1u64, 2, 3].map(|i| format!("{:016}", i).into_bytes()));
+        assert_eq!(entries[0].1, b"latest");
+
+        let reader = TableReader::open(temp_dir.path().join("000000.sst")).unwrap();
+        assert_eq!(reader.get(format!("{:016}", 2).as_bytes()).unwrap(), Some(b"value2".to_vec()));
+        assert_eq!(reader.get(format!("{:016}", 4).as_bytes()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_open_table_uses_block_cache() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let options = Options::builder()
+            .write_buffer_size(1024 * 1024)
+            .block_cache_size(1024 * 1024)
+            .build()
+            .unwrap();
+        let write_path = WritePath::open(temp_dir.path(), options).unwrap();
+        for i in 0..100u64 {
+            write_path.put(format!("{:016}", i).into_bytes(), vec![b'v'; 100]).unwrap();
+        }
+        write_path.flush_and_wait().unwrap();
+
+        let key = format!("{:016}", 42).into_bytes();
+        for _ in 0..2 {
+            let reader = write_path.open_table(0).unwrap();
+            assert_eq!(reader.get(&key).unwrap(), Some(vec![b'v'; 100]));
+        }
+        let stats = write_path.block_cache().unwrap().stats();
+        assert_eq!((stats.data_hits, stats.data_misses), (1, 1));
+        // 2回目はテーブルキャッシュのTableReaderを使う
+        assert_eq!(write_path.table_cache().len(), 1);
+    }
+
+    #[test]
+    fn test_perf_context() {
+        use crate::perf_context::{perf_context, reset_perf_context, set_perf_level, PerfLevel};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let options = Options::builder()
+            .write_buffer_size(1024 * 1024)
+            .block_cache_size(1024 * 1024)
+            .build()
+            .unwrap();
+        let write_path = WritePath::open(temp_dir.path(), options).unwrap();
+
+        set_perf_level(PerfLevel::EnableTime);
+        reset_perf_context();
+        for i in 0..100u64 {
+            write_path.put(format!("{:016}", i).into_bytes(), vec![b'v'; 100]).unwrap();
+        }
+        let ctx = perf_context();
+        assert!(ctx.write_memtable_nanos > 0, "{}", ctx);
+        assert_eq!((ctx.write_stall_nanos, ctx.write_wal_nanos), (0, 0));
+        write_path.flush_and_wait().unwrap();
+
+        // 1回目はファイルから読み、2回目はブロックキャッシュから読む
+        let key = format!("{:016}", 42).into_bytes();
+        reset_perf_context();
+        write_path.open_table(0).unwrap().get(&key).unwrap();
+        let ctx = perf_context();
+        assert!(ctx.block_read_count > 0 && ctx.block_read_bytes > 0, "{}", ctx);
+        assert_eq!(ctx.block_cache_hit_count, 0);
+
+        reset_perf_context();
+        write_path.open_table(0).unwrap().get(&key).unwrap();
+        let ctx = perf_context();
+        assert_eq!(ctx.block_read_count, 0);
+        assert!(ctx.block_cache_hit_count > 0, "{}", ctx);
+        set_perf_level(PerfLevel::Disable);
+    }
+
+    #[test]
+    fn test_flush_goes_through_rate_limiter() {
+        use crate::RateLimiter;
+        use std::time::{Duration, Instant};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        // 10msごとに16KB
+        let limiter = Arc::new(RateLimiter::with_options(1600 * 1024, Duration::from_millis(10), 10, false));
+        let options = Options::builder()
+            .write_buffer_size(1024 * 1024)
+            .rate_limiter(Arc::clone(&limiter))
+            .build()
+            .unwrap();
+        let write_path = WritePath::open(temp_dir.path(), options).unwrap();
+        for i in 0..1000u64 {
+            write_path.put(format!("{:016}", i).into_bytes(), vec![b'v'; 100]).unwrap();
+        }
+        let start = Instant::now();
+        write_path.flush_and_wait().unwrap();
+
+        // SSTableのバイト数だけHighで要求し、補充を待つ
+        let file_size = fs::metadata(temp_dir.path().join("000000.sst")).unwrap().len();
+        assert_eq!(limiter.total_bytes_through(IoPriority::High), file_size);
+        assert!(start.elapsed() >= Duration::from_millis(50), "{:?}", start.elapsed());
+        assert_eq!(read_entries(temp_dir.path()).len(), 1000);
+    }
+
+    #[test]
+    fn test_statistics() {
+        use crate::Statistics;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let stats = Arc::new(Statistics::new());
+        let options = Options::builder()
+            .write_buffer_size(10 * 1024)
+            .statistics(Arc::clone(&stats))
+            .build()
+            .unwrap();
+        let write_path = WritePath::open(temp_dir.path(), options).unwrap();
+        for i in 0..100u64 {
+            write_path.put(format!("{:06}", i).into_bytes(), vec![b'v'; 994]).unwrap();
+        }
+        write_path.flush_and_wait().unwrap();
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.ticker(Ticker::KeysWritten), 100);
+        assert_eq!(snapshot.ticker(Ticker::BytesWritten), 100 * 1000);
+        assert_eq!(snapshot.histogram(Histogram::PutMicros).count, 100);
+
+        // 書き出したSSTableごとに記録される
+        let mut file_sizes = Vec::new();
+        for entry in fs::read_dir(temp_dir.path()).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "sst") {
+                file_sizes.push(fs::metadata(path).unwrap().len());
+            }
+        }
+        assert_eq!(snapshot.ticker(Ticker::Flushes), file_sizes.len() as u64);
+        assert_eq!(snapshot.ticker(Ticker::FlushBytesWritten), file_sizes.iter().sum::<u64>());
+        let sst_size = snapshot.histogram(Histogram::SstableSize);
+        assert_eq!(sst_size.max, *file_sizes.iter().max().unwrap());
+        assert_eq!(snapshot.histogram(Histogram::FlushMicros).count, file_sizes.len() as u64);
+        assert_eq!(snapshot.ticker(Ticker::WalSyncs), 0);
+    }
+
+    #[test]
+    fn test_event_listener() {
+        use crate::event_listener::CompactionJobInfo;
+
+        #[derive(Default)]
+        struct Recorder {
+            events: Mutex<Vec<String>>,
+            flushes: Mutex<Vec<FlushJobInfo>>,
+        }
+        impl EventListener for Recorder {
+            fn on_memtable_sealed(&self, info: &MemTableInfo) {
+                self.events.lock().unwrap().push(format!("sealed {}", info.num_entries));
+            }
+            fn on_flush_begin(&self, info: &FlushJobInfo) {
+                self.events.lock().unwrap().push(format!("begin {}", info.file_number));
+            }
+            fn on_flush_completed(&self, info: &FlushJobInfo) {
+                self.events.lock().unwrap().push(format!("completed {}", info.file_number));
+                self.flushes.lock().unwrap().push(info.clone());
+            }
+            fn on_compaction_completed(&self, _info: &CompactionJobInfo) {
+                self.events.lock().unwrap().push("compaction".to_string());
+            }
+            fn on_stall_conditions_changed(&self, info: &WriteStallInfo) {
+                self.events.lock().unwrap().push(format!("stall {:?}", info.cur));
+            }
+            fn on_background_error(&self, error: &Error) {
+                self.events.lock().unwrap().push(format!("error {}", error));
+            }
+        }
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let data_dir = temp_dir.path().join("db");
+        let recorder = Arc::new(Recorder::default());
+        let options = Options::builder()
+            .write_buffer_size(1024 * 1024)
+            .add_listener(recorder.clone())
+            .build()
+            .unwrap();
+        let write_path = WritePath::open(&data_dir, options).unwrap();
+        write_path.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        write_path.put(b"a".to_vec(), b"2".to_vec()).unwrap();
+        write_path.put(b"b".to_vec(), b"3".to_vec()).unwrap();
+        write_path.flush_and_wait().unwrap();
+
+        assert_eq!(*recorder.events.lock().unwrap(), vec!["sealed 3", "begin 0", "completed 0"]);
+        let flushes = recorder.flushes.lock().unwrap().clone();
+        assert_eq!(flushes[0].file_path, data_dir.join("000000.sst"));
+        assert_eq!(flushes[0].file_size, fs::metadata(&flushes[0].file_path).unwrap().len());
+        assert_eq!(flushes[0].num_entries, 2);
+
+        // フラッシュの失敗はバックグラウンドエラーとして通知される
+        // （状態のロックを解放してから通知するので、flush_and_waitが先に戻ることがある）
+        recorder.events.lock().unwrap().clear();
+        fs::remove_dir_all(&data_dir).unwrap();
+        write_path.put(b"c".to_vec(), b"4".to_vec()).unwrap();
+        assert!(write_path.flush_and_wait().is_err());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while recorder.events.lock().unwrap().len() < 3 {
+            assert!(Instant::now() < deadline, "background error was not notified");
+            thread::sleep(Duration::from_millis(1));
+        }
+        let events = recorder.events.lock().unwrap().clone();
+        assert_eq!(events[..2], ["sealed 1", "begin 1"]);
+        assert!(events[2].starts_with("error IO error"), "{:?}", events);
+        assert_eq!(events.len(), 3);
+    }
+
+    #[test]
+    fn test_kept_fatal_error_is_not_notified() {
+        let shared = Shared::new(0, Vec::new());
+        shared.set_fatal(&mut shared.lock_state(), "fatal".to_string());
+        assert_eq!(shared.events.lock().unwrap().len(), 1);
+
+        // 致命的エラーは上書きしないので、後からのエラーは通知しない
+        let e = Arc::new(Error::Io(io::Error::other("later error")));
+        shared.set_error(&mut shared.lock_state(), e);
+        assert_eq!(shared.events.lock().unwrap().len(), 1);
+        assert!(matches!(shared.check_bg_error(), Err(Error::Fatal(_))));
+    }
+
+    #[test]
+    fn test_stall_conditions_changed() {
+        /// 最初のフラッシュをreleaseされるまで止め、write stallの通知を記録するリスナー
+        #[derive(Default)]
+        struct StallRecorder {
+            /// (フラッシュが始まった, 止めているフラッシュを進めてよい)
+            gate: Mutex<(bool, bool)>,
+            cv: Condvar,
+            stalls: Mutex<Vec<WriteStallInfo>>,
+        }
+        impl EventListener for StallRecorder {
+            fn on_flush_begin(&self, _info: &FlushJobInfo) {
+                let mut gate = self.gate.lock().unwrap();
+                gate.0 = true;
+                self.cv.notify_all();
+                while !gate.1 {
+                    gate = self.cv.wait(gate).unwrap();
+                }
+            }
+            fn on_stall_conditions_changed(&self, info: &WriteStallInfo) {
+                self.stalls.lock().unwrap().push(*info);
+            }
+        }
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let recorder = Arc::new(StallRecorder::default());
+        // immutable MemTableは1つまで
+        let options = Options::builder()
+            .write_buffer_size(1)
+            .max_write_buffer_number(2)
+            .add_listener(recorder.clone())
+            .build()
+            .unwrap();
+        let write_path = WritePath::open(temp_dir.path(), options).unwrap();
+
+        // 1つ目はフラッシュスレッドが書き出し中、2つ目はチャネルで待つ
+        write_path.put(b"key1".to_vec(), b"value1".to_vec()).unwrap();
+        let gate = recorder.gate.lock().unwrap();
+        drop(recorder.cv.wait_while(gate, |gate| !gate.0).unwrap());
+        write_path.put(b"key2".to_vec(), b"value2".to_vec()).unwrap();
+        assert!(recorder.stalls.lock().unwrap().is_empty());
+
+        thread::scope(|scope| {
+            // 3つ目の書き込みはwrite stallで止まる
+            let writer = scope.spawn(|| write_path.put(b"key3".to_vec(), b"value3".to_vec()));
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !write_path.gauges().write_stalled {
+                assert!(Instant::now() < deadline, "write was not stalled");
+                thread::sleep(Duration::from_millis(1));
+            }
+            recorder.gate.lock().unwrap().1 = true;
+            recorder.cv.notify_all();
+            writer.join().unwrap().unwrap();
+        });
+
+        // 起きた順に通知される
+        let stalls = recorder.stalls.lock().unwrap().clone();
+        assert_eq!(
+            stalls,
+            [
+                WriteStallInfo {
+                    prev: WriteStallCondition::Normal,
+                    cur: WriteStallCondition::Stopped,
+                },
+                WriteStallInfo {
+                    prev: WriteStallCondition::Stopped,
+                    cur: WriteStallCondition::Normal,
+                },
+            ]
+        );
+        assert!(!write_path.gauges().write_stalled);
+        write_path.close().unwrap();
+        assert_eq!(read_entries(temp_dir.path()).len(), 3);
+    }
+
+    #[test]
+    fn test_freeze_without_flush_worker_keeps_memtable() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let write_path = WritePath::open(temp_dir.path(), test_options(1)).unwrap();
+        // 送信先がない（シャットダウン中）状態にする
+        drop(write_path.flush_sender.lock().unwrap().take());
+
+        let err = write_path.put(b"key1".to_vec(), b"value1".to_vec()).unwrap_err();
+        assert!(matches!(err, Error::ShutdownInProgress), "{:?}", err);
+        assert!(matches!(write_path.flush(), Err(Error::ShutdownInProgress)));
+        // 断った書き込みはmemtableに残っている
+        assert_eq!(write_path.memtable.lock().unwrap().entries.len(), 1);
+        assert_eq!(write_path.gauges().memtable_size, 10);
+
+        // 送信先が戻れば書き出される
+        write_path.start_flush_worker();
+        write_path.close().unwrap();
+        assert!(contains_value(&read_entries(temp_dir.path()), b"value1"));
+    }
+
+    #[test]
+    fn test_no_slowdown_refuses_stalled_write() {
+        /// 最初のフラッシュをreleaseされるまで止めるリスナー
+        #[derive(Default)]
+        struct FlushGate {
+            /// (フラッシュが始まった, 止めているフラッシュを進めてよい)
+            gate: Mutex<(bool, bool)>,
+            cv: Condvar,
+        }
+        impl EventListener for FlushGate {
+            fn on_flush_begin(&self, _info: &FlushJobInfo) {
+                let mut gate = self.gate.lock().unwrap();
+                gate.0 = true;
+                self.cv.notify_all();
+                while !gate.1 {
+                    gate = self.cv.wait(gate).unwrap();
+                }
+            }
+        }
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let flush_gate = Arc::new(FlushGate::default());
+        let options = Options::builder()
+            .write_buffer_size(1)
+            .max_write_buffer_number(2)
+            .add_listener(flush_gate.clone())
+            .build()
+            .unwrap();
+        let write_path = WritePath::open(temp_dir.path(), options).unwrap();
+        let no_slowdown = WriteOptions { no_slowdown: true };
+
+        // 1つ目はフラッシュスレッドが書き出し中、2つ目はチャネルで待つ
+        write_path.put_with(b"key1".to_vec(), b"value1".to_vec(), &no_slowdown).unwrap();
+        let gate = flush_gate.gate.lock().unwrap();
+        drop(flush_gate.cv.wait_while(gate, |gate| !gate.0).unwrap());
+        write_path.put_with(b"key2".to_vec(), b"value2".to_vec(), &no_slowdown).unwrap();
+
+        // 3つ目はwrite stallになるので、待たずに断られて反映されない
+        let err = write_path.put_with(b"key3".to_vec(), b"value3".to_vec(), &no_slowdown).unwrap_err();
+        assert!(matches!(err, Error::WriteStall), "{:?}", err);
+        let gauges = write_path.gauges();
+        assert_eq!((gauges.memtable_size, gauges.immutable_memtables), (0, 2));
+        assert!(!gauges.write_stalled);
+
+        // フラッシュが進めば書き込める
+        flush_gate.gate.lock().unwrap().1 = true;
+        flush_gate.cv.notify_all();
+        write_path.flush_and_wait().unwrap();
+        write_path.put_with(b"key4".to_vec(), b"value4".to_vec(), &no_slowdown).unwrap();
+        write_path.close().unwrap();
+
+        let entries = read_entries(temp_dir.path());
+        assert_eq!(entries.len(), 3);
+        assert!(!contains_value(&entries, b"value3"));
+        assert!(contains_value(&entries, b"value4"));
+    }
+
+    #[test]
+    fn test_info_log() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let write_path = WritePath::open(temp_dir.path(), test_options(1024 * 1024)).unwrap();
+        write_path.put(b"key".to_vec(), b"value".to_vec()).unwrap();
+        write_path.flush_and_wait().unwrap();
+        write_path.info_log.dump_stats(None);
+        write_path.close().unwrap();
+
+        let log = fs::read_to_string(temp_dir.path().join("LOG")).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert!(lines[0].contains(" WritePath opened: "), "{}", log);
+        assert!(log.contains(" Options.write_buffer_size=1048576\n"), "{}", log);
+        assert!(log.contains(" [flush] finished: file #0 "), "{}", log);
+        assert!(log.contains(" ------- DUMPING STATS -------\n"), "{}", log);
+        assert!(log.contains(" L0         1 "), "{}", log);
+        assert!(lines.last().unwrap().ends_with(" WritePath closed"), "{}", log);
+    }
+
+    /// 作られたspanの名前とfollows_fromの関係を記録するだけのSubscriber
+    #[cfg(feature = "tracing")]
+    #[derive(Default)]
+    struct SpanRecorder {
+        /// spanの名前（IDの順）
+        names: Mutex<Vec<&'static str>>,
+        /// (span, 先に起きたspan)
+        follows: Mutex<Vec<(u64, u64)>>,
+    }
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::Id {
+            let mut names = self.names.lock().unwrap();
+            names.push(span.metadata().name());
+            tracing::Id::from_u64(names.len() as u64)
+        }
+        fn record(&self, _span: &tracing::Id, _values: &tracing::span::Record<'_>) {}
+        fn record_follows_from(&self, span: &tracing::Id, follows: &tracing::Id) {
+            self.follows.lock().unwrap().push((span.into_u64(), follows.into_u64()));
+        }
+        fn event(&self, _event: &tracing::Event<'_>) {}
+        fn enter(&self, _span: &tracing::Id) {}
+        fn exit(&self, _span: &tracing::Id) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_tracing_spans() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let recorder = Arc::new(SpanRecorder::default());
+        // with_defaultはこのスレッドだけなので、フラッシュスレッドのspanは記録されない
+        tracing::subscriber::with_default(recorder.clone(), || {
+            // 書き込みサイズの閾値を超えるのでputの中でfreeze_memtableが呼ばれる
+            let write_path = WritePath::open(temp_dir.path(), test_options(10)).unwrap();
+            write_path.put(b"key".to_vec(), b"0123456789".to_vec()).unwrap();
+            write_path.resume().unwrap();
+        });
+        assert_eq!(*recorder.names.lock().unwrap(), vec!["open", "put", "freeze_memtable", "resume"]);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_flush_span_follows_from_freeze_memtable() {
+        // フラッシュスレッドのspanも記録するため、グローバルに設定する
+        // （このテストだけが設定する。他のテストのspanも記録されるが、関係を見るだけなので問題ない）
+        let recorder = Arc::new(SpanRecorder::default());
+        tracing::subscriber::set_global_default(recorder.clone()).unwrap();
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let write_path = WritePath::open(temp_dir.path(), test_options(1024 * 1024)).unwrap();
+        write_path.put(b"key".to_vec(), b"value".to_vec()).unwrap();
+        write_path.flush_and_wait().unwrap();
+        write_path.close().unwrap();
+
+        let names = recorder.names.lock().unwrap();
+        let name = |id: u64| names[id as usize - 1];
+        assert!(names.contains(&"flush"), "{:?}", names);
+        let follows = recorder.follows.lock().unwrap();
+        assert!(
+            follows.iter().any(|&(span, cause)| name(span) == "flush" && name(cause) == "freeze_memtable"),
+            "{:?}",
+            follows
+        );
+    }
+
+    #[test]
+    fn test_direct_io_writes_and_reads() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let options = Options::builder()
+            .write_buffer_size(1024 * 1024)
+            .use_direct_io_for_flush_and_compaction(true)
+            .use_direct_reads(true)
+            .sync_policy(SyncPolicy {
+                sync_sstables: true,
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        let write_path = WritePath::open(temp_dir.path(), options).unwrap();
+        for i in 0..1000u64 {
+            write_path.put(format!("{:016}", i).into_bytes(), vec![b'v'; 100]).unwrap();
+        }
+        write_path.flush_and_wait().unwrap();
+
+        // Direct I/Oに対応していないファイルシステムでは通常のI/Oで書かれる
+        let reader = write_path.open_table(0).unwrap();
+        assert_eq!(reader.iter().count(), 1000);
+        assert_eq!(reader.get(format!("{:016}", 999).as_bytes()).unwrap(), Some(vec![b'v'; 100]));
+        assert_eq!(read_entries(temp_dir.path()).len(), 1000);
+    }
+
+    #[test]
+    fn test_sync_policy_writes_complete_sstables() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let options = Options::builder()
+            .write_buffer_size(1024)
+            .sync_policy(SyncPolicy {
+                sync_sstables: true,
+                bytes_per_sync: 64,
+                sync_dir: true,
+            })
+            .build()
+            .unwrap();
+        let write_path = WritePath::open(temp_dir.path(), options).unwrap();
+
+        for i in 0..100 {
+            let key = format!("key{:03}", i).into_bytes();
+            write_path.put(key, vec![b'x'; 50]).unwrap();
+        }
+        write_path.flush_and_wait().unwrap();
+
+        let names: Vec<String> = fs::read_dir(temp_dir.path())
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect();
+        assert!(names.iter().any(|name| name.ends_with(".sst")));
+        // 一時ファイルはすべてrename済み
+        assert!(names.iter().all(|name| !name.ends_with(".tmp")), "{:?}", names);
+    }
+
+    #[test]
+    fn test_mem_file_system() {
+        let fs = crate::MemFileSystem::new();
+        // ホストに実在するパスを使い、そこに何も書かれないことを確かめる
+        let temp_dir = tempfile::tempdir().unwrap();
+        let data_dir = &temp_dir.path().join("db");
+        let options = Options::builder()
+            .write_buffer_size(1024 * 1024)
+            .allow_mmap_reads(true)
+            .build()
+            .unwrap();
+
+        let write_path = WritePath::open_with_file_system(data_dir, options, Arc::new(fs.clone())).unwrap();
+        write_path.put(b"key1".to_vec(), b"value1".to_vec()).unwrap();
+        write_path.flush_and_wait().unwrap();
+        drop(write_path);
+
+        // ディスクには何も書かれず、開き直すと続きの番号から採番する
+        assert!(!data_dir.exists());
+        let write_path = WritePath::reopen_with_file_system(data_dir, Arc::new(fs.clone())).unwrap();
+        write_path.put(b"key2".to_vec(), b"value2".to_vec()).unwrap();
+        write_path.flush_and_wait().unwrap();
+
+        let mut names = fs.list_dir(data_dir).unwrap();
+        names.sort();
+        // 開き直したときに前回のLOGはLOG.old.*になる
+        assert_eq!(names.len(), 6);
+        assert_eq!(names[..4], ["000000.sst", "000001.sst", "LOCK", "LOG"]);
+        assert!(names[4].starts_with("LOG.old."), "{:?}", names);
+        assert_eq!(names[5], "OPTIONS");
+        // mmapできないファイルシステムでは通常の読み込みになる
+        assert_eq!(write_path.open_table(0).unwrap().get(b"key1").unwrap(), Some(b"value1".to_vec()));
+        assert_eq!(write_path.open_table(1).unwrap().get(b"key2").unwrap(), Some(b"value2".to_vec()));
+    }
+
+    /// put/flushの途中のランダムな時点で電源断を起こし、開き直して
+    /// fsyncまで完了した書き込みがすべて残っていて、SSTableが壊れていないことを確かめる
+    #[test]
+    fn test_crash_consistency() {
+        use crate::FaultInjectionFileSystem;
+        use std::collections::BTreeMap;
+
+        let fs = FaultInjectionFileSystem::new(Arc::new(crate::MemFileSystem::new()));
+        let data_dir = Path::new("/db");
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut random = move |n: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % n
+        };
+
+        let options = Options::builder()
+            .write_buffer_size(512)
+            .max_write_buffer_number(3)
+            .sync_policy(SyncPolicy {
+                sync_sstables: true,
+                bytes_per_sync: 0,
+                sync_dir: false,
+            })
+            .build()
+            .unwrap();
+        drop(WritePath::open_with_file_system(data_dir, options, Arc::new(fs.clone())).unwrap());
+
+        // flush_withが成功した時点で永続化されている書き込み
+        let mut synced: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+        let mut next_key = 0u64;
+        for _ in 0..50 {
+            // 開き直している途中で電源断になることもある
+            fs.power_cut_after(random(300));
+            let write_path = WritePath::reopen_with_file_system(data_dir, Arc::new(fs.clone()));
+
+            let mut unsynced = Vec::new();
+            while let Ok(write_path) = &write_path {
+                let key = format!("key{:08}", next_key).into_bytes();
+                let value = format!("value{}-{}", next_key, "x".repeat(random(64) as usize)).into_bytes();
+                next_key += 1;
+                if write_path.put(key.clone(), value.clone()).is_err() {
+                    break;
+                }
+                unsynced.push((key, value));
+
+                if random(10) == 0 {
+                    let options = FlushOptions { wait: true, sync_dir: true };
+                    if write_path.flush_with(&options).is_ok() {
+                        synced.extend(unsynced.drain(..));
+                    }
+                }
+            }
+            drop(write_path);
+            fs.drop_unsynced_data().unwrap();
+
+            // 開き直して、残っているSSTableがすべて読めることを確かめる
+            let write_path = WritePath::reopen_with_file_system(data_dir, Arc::new(fs.clone())).unwrap();
+            let mut found: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+            for name in fs.list_dir(data_dir).unwrap() {
+                let Some(number) = name.strip_suffix(".sst").and_then(|n| n.parse::<u64>().ok()) else {
+                    continue;
+                };
+                let reader = write_path.open_table(number).unwrap();
+                for entry in reader.iter() {
+                    let (key, value) = entry.unwrap();
+                    found.insert(key, value);
+                }
+            }
+            for (key, value) in &synced {
+                assert_eq!(found.get(key), Some(value), "synced write {:?} was lost", key);
+            }
+            // 書き出しが間に合ったものは、同期していなくても残っていてよい
+            synced = found;
+        }
+        assert!(synced.len() > 100, "too few writes survived: {}", synced.len());
+    }
+
+    /// rename後のsync_dirに失敗しても、SSTableは設置済みとして扱い
+    /// MemTableを再試行しない（同じデータを別の番号で二重に書き出さない）
+    #[test]
+    fn test_dir_sync_failure_after_rename() {
+        use crate::FaultInjectionFileSystem;
+
+        let fs = FaultInjectionFileSystem::new(Arc::new(crate::MemFileSystem::new()));
+        let data_dir = Path::new("/db");
+        let options = Options::builder()
+            .write_buffer_size(1024 * 1024)
+            .sync_policy(SyncPolicy {
+                sync_sstables: false,
+                bytes_per_sync: 0,
+                sync_dir: true,
+            })
+            .build()
+            .unwrap();
+        let write_path = WritePath::open_with_file_system(data_dir, options, Arc::new(fs.clone())).unwrap();
+        let sst_names = || {
+            let mut names: Vec<String> =
+                fs.list_dir(data_dir).unwrap().into_iter().filter(|name| name.ends_with(".sst")).collect();
+            names.sort();
+            names
+        };
+
+        // フラッシュスレッドのsync_dirとflush_and_waitのfsyncが失敗する
+        fs.fail_syncs_after(0);
+        write_path.put(b"key1".to_vec(), b"value1".to_vec()).unwrap();
+        assert!(matches!(write_path.flush_and_wait(), Err(Error::Io(_))));
+        assert!(!write_path.shared.read_only.load(Ordering::Acquire));
+        assert_eq!(sst_names(), ["000000.sst"]);
+
+        // 障害がなくなれば、FlushOptions::sync_dirがなくてもsync_dirをやり直す
+        fs.clear_faults();
+        write_path.flush_and_wait().unwrap();
+        drop(write_path);
+        fs.drop_unsynced_data().unwrap();
+
+        assert_eq!(sst_names(), ["000000.sst"]);
+        let write_path = WritePath::reopen_with_file_system(data_dir, Arc::new(fs.clone())).unwrap();
+        assert_eq!(write_path.open_table(0).unwrap().get(b"key1").unwrap(), Some(b"value1".to_vec()));
+    }
+
+    #[test]
+    fn test_reopen_uses_saved_options_and_keeps_sstables() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let options = Options::builder()
+            .write_buffer_size(2048)
+            .max_write_buffer_number(3)
+            .build()
+            .unwrap();
+
+        let write_path = WritePath::open(temp_dir.path(), options.clone()).unwrap();
+        write_path.put(b"key1".to_vec(), b"value1".to_vec()).unwrap();
+        write_path.flush_and_wait().unwrap();
+        drop(write_path);
+
+        let write_path = WritePath::reopen(temp_dir.path()).unwrap();
+        assert_eq!(write_path.options(), &options);
+        write_path.put(b"key2".to_vec(), b"value2".to_vec()).unwrap();
+        write_path.flush_and_wait().unwrap();
+        drop(write_path);
+
+        // 開き直しても既存のSSTableは上書きされない
+        let mut names: Vec<String> = fs::read_dir(temp_dir.path())
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".sst"))
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["000000.sst", "000001.sst"]);
+    }
+
+    #[test]
+    fn test_lock_file_prevents_second_open() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let write_path = WritePath::open(temp_dir.path(), test_options(1024)).unwrap();
+
+        // 同じプロセスの別のインスタンスでも開けない
+        let err = WritePath::reopen(temp_dir.path()).err().unwrap();
+        assert!(matches!(err, Error::Busy(_)), "{:?}", err);
+
+        // 閉じればロックが解放される
+        write_path.close().unwrap();
+        let write_path = WritePath::reopen(temp_dir.path()).unwrap();
+        drop(write_path);
+        WritePath::reopen(temp_dir.path()).unwrap();
+    }
+
+    #[test]
+    fn test_poisoned_memtable_lock_is_fatal() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let write_path = Arc::new(WritePath::open(temp_dir.path(), test_options(1024)).unwrap());
+
+        // memtableのロックを持ったままパニックさせる
+        let write_path_clone = write_path.clone();
+        let result = std::thread::spawn(move || {
+            let _memtable = write_path_clone.memtable.lock().unwrap();
+            panic!("writer panicked while holding the memtable lock");
+        })
+        .join();
+        assert!(result.is_err());
+
+        // プロセスごと落ちずに致命的エラーが返る
+        let err = write_path.put(b"key1".to_vec(), b"value1".to_vec()).unwrap_err();
+        assert!(matches!(err, Error::Fatal(_)), "{:?}", err);
+        assert!(matches!(write_path.flush(), Err(Error::Fatal(_))));
+        assert!(matches!(write_path.resume(), Err(Error::Fatal(_))));
+    }
+
+    /// `.sst.tmp`の作成でパニックするファイルシステム
+    #[derive(Debug, Default)]
+    struct PanickingFileSystem {
+        inner: crate::MemFileSystem,
+        panic_on_flush: AtomicBool,
+    }
+
+    impl FileSystem for PanickingFileSystem {
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            self.inner.create_dir_all(path)
+        }
+        fn new_writable_file(
+            &self,
+            path: &Path,
+            options: &FileOptions,
+        ) -> io::Result<Box<dyn crate::file_system::WritableFile>> {
+            if self.panic_on_flush.load(Ordering::SeqCst) && path.to_string_lossy().ends_with(".sst.tmp") {
+                panic!("injected panic while creating {}", path.display());
+            }
+            self.inner.new_writable_file(path, options)
+        }
+        fn new_random_access_file(
+            &self,
+            path: &Path,
+            options: &FileOptions,
+        ) -> io::Result<Box<dyn crate::file_system::RandomAccessFile>> {
+            self.inner.new_random_access_file(path, options)
+        }
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            self.inner.rename(from, to)
+        }
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            self.inner.remove_file(path)
+        }
+        fn list_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+            self.inner.list_dir(path)
+        }
+        fn sync_file(&self, path: &Path) -> io::Result<()> {
+            self.inner.sync_file(path)
+        }
+        fn sync_dir(&self, path: &Path) -> io::Result<()> {
+            self.inner.sync_dir(path)
+        }
+        fn lock_file(&self, path: &Path) -> io::Result<Box<dyn FileLock>> {
+            self.inner.lock_file(path)
+        }
+    }
+
+    /// data_dirのすべてのSSTableの値
+    fn table_values(write_path: &WritePath) -> Vec<Vec<u8>> {
+        let mut values = Vec::new();
+        for name in write_path.fs.list_dir(&write_path.data_dir).unwrap() {
+            if let Some(number) = name.strip_suffix(".sst").and_then(|n| n.parse::<u64>().ok()) {
+                for entry in write_path.open_table(number).unwrap().iter() {
+                    values.push(entry.unwrap().1);
+                }
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn test_flush_panic_is_fatal_and_resumable() {
+        use std::time::{Duration, Instant};
+
+        let fs = Arc::new(PanickingFileSystem::default());
+        let write_path = WritePath::open_with_file_system("/db", test_options(1024), fs.clone()).unwrap();
+
+        fs.panic_on_flush.store(true, Ordering::SeqCst);
+        write_path.put(b"key1".to_vec(), b"value1".to_vec()).unwrap();
+        write_path.flush().unwrap();
+
+        // SSTableの書き出し中のパニックは致命的エラーとして返る
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let err = loop {
+            match write_path.put(b"key2".to_vec(), b"value2".to_vec()) {
+                Err(e) => break e,
+                Ok(()) => {
+                    assert!(Instant::now() < deadline, "flush panic was not reported");
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+        };
+        assert!(matches!(&err, Error::Fatal(msg) if msg.contains("injected panic")), "{:?}", err);
+        assert!(matches!(write_path.flush_and_wait(), Err(Error::Fatal(_))));
+        // 原因が残っている間はresumeもパニックせずに失敗する
+        assert!(matches!(write_path.resume(), Err(Error::BackgroundError(_))));
+
+        // フラッシュスレッドは動き続けており、保持していたMemTableが書き出される
+        fs.panic_on_flush.store(false, Ordering::SeqCst);
+        write_path.resume().unwrap();
+        assert!(!write_path.flush_worker_is_dead());
+        write_path.put(b"key3".to_vec(), b"value3".to_vec()).unwrap();
+        write_path.flush_and_wait().unwrap();
+
+        let values = table_values(&write_path);
+        assert!(values.contains(&b"value1".to_vec()), "memtable kept for retry should be flushed");
+        assert!(values.contains(&b"value3".to_vec()));
+    }
+
+    #[test]
+    fn test_dead_flush_worker_is_restarted_on_resume() {
+        use std::time::{Duration, Instant};
+
+        /// 最初のバックグラウンドエラーの通知でパニックするリスナー
+        struct PanicOnce(AtomicBool);
+        impl EventListener for PanicOnce {
+            fn on_background_error(&self, _error: &Error) {
+                if self.0.swap(false, Ordering::SeqCst) {
+                    panic!("listener panicked");
+                }
+            }
+        }
+
+        let fs = Arc::new(PanickingFileSystem::default());
+        let options = Options::builder()
+            .write_buffer_size(1024)
+            .restart_flush_worker(true)
+            .add_listener(Arc::new(PanicOnce(AtomicBool::new(true))))
+            .build()
+            .unwrap();
+        let write_path = WritePath::open_with_file_system("/db", options, fs.clone()).unwrap();
+
+        // 書き出しの失敗を通知したリスナーのパニックでフラッシュスレッドが終了する
+        fs.panic_on_flush.store(true, Ordering::SeqCst);
+        write_path.put(b"key1".to_vec(), b"value1".to_vec()).unwrap();
+        write_path.flush().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !write_path.flush_worker_is_dead() {
+            assert!(Instant::now() < deadline, "flush worker did not exit");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let result = write_path.put(b"key2".to_vec(), b"value2".to_vec()).and_then(|()| write_path.flush());
+        assert!(matches!(result, Err(Error::Fatal(_))), "{:?}", result);
+
+        // resumeでスレッドを再起動し、失敗したMemTableも書き出される
+        fs.panic_on_flush.store(false, Ordering::SeqCst);
+        write_path.resume().unwrap();
+        assert!(!write_path.flush_worker_is_dead());
+        write_path.put(b"key3".to_vec(), b"value3".to_vec()).unwrap();
+        write_path.flush_and_wait().unwrap();
+
+        let values = table_values(&write_path);
+        assert!(values.contains(&b"value1".to_vec()), "memtable held by the dead worker should be flushed");
+        assert!(values.contains(&b"value3".to_vec()));
+    }
+
+    #[test]
+    fn test_close_reports_errors() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let write_path = WritePath::open(temp_dir.path(), test_options(1024)).unwrap();
+        write_path.put(b"key1".to_vec(), b"value1".to_vec()).unwrap();
+        write_path.close().unwrap();
+
+        let sst_count = |dir: &Path| {
+            fs::read_dir(dir)
+                .unwrap()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().extension().is_some_and(|ext| ext == "sst"))
+                .count()
+        };
+        assert_eq!(sst_count(temp_dir.path()), 1);
+
+        // フラッシュに失敗した場合はdropと違ってエラーが返る
+        let data_dir = temp_dir.path().join("db");
+        let write_path = WritePath::open(&data_dir, test_options(1024)).unwrap();
+        fs::remove_dir_all(&data_dir).unwrap();
+        write_path.put(b"key1".to_vec(), b"value1".to_vec()).unwrap();
+        assert!(matches!(write_path.close(), Err(Error::BackgroundError(_))));
+
+        // 打ち切る場合もread-onlyモードならバックグラウンドエラーを返す
+        let write_path = WritePath::open(&data_dir, test_options(1024)).unwrap();
+        fs::remove_dir_all(&data_dir).unwrap();
+        write_path.put(b"key1".to_vec(), b"value1".to_vec()).unwrap();
+        assert!(write_path.flush_and_wait().is_err());
+        let options = CloseOptions { cancel_background_work: true };
+        assert!(matches!(write_path.close_with(&options), Err(Error::BackgroundError(_))));
+    }
+
+    /// dropはフラッシュするがfsyncしない。close()はfsyncまで行う
+    #[test]
+    fn test_drop_does_not_sync_but_close_does() {
+        use crate::FaultInjectionFileSystem;
+
+        let fs = FaultInjectionFileSystem::new(Arc::new(crate::MemFileSystem::new()));
+        let data_dir = Path::new("/db");
+        let sst_count = || fs.list_dir(data_dir).unwrap().iter().filter(|name| name.ends_with(".sst")).count();
+
+        let write_path =
+            WritePath::open_with_file_system(data_dir, test_options(1024 * 1024), Arc::new(fs.clone())).unwrap();
+        write_path.put(b"key1".to_vec(), b"value1".to_vec()).unwrap();
+        drop(write_path);
+        assert_eq!(sst_count(), 1);
+        fs.drop_unsynced_data().unwrap();
+        assert_eq!(sst_count(), 0);
+
+        let write_path = WritePath::reopen_with_file_system(data_dir, Arc::new(fs.clone())).unwrap();
+        write_path.put(b"key2".to_vec(), b"value2".to_vec()).unwrap();
+        write_path.close().unwrap();
+        fs.drop_unsynced_data().unwrap();
+        assert_eq!(sst_count(), 1);
+        let write_path = WritePath::reopen_with_file_system(data_dir, Arc::new(fs.clone())).unwrap();
+        assert_eq!(write_path.open_table(0).unwrap().get(b"key2").unwrap(), Some(b"value2".to_vec()));
+    }
+
+    #[test]
+    fn test_close_with_cancel_background_work() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let write_path = WritePath::open(temp_dir.path(), test_options(1024)).unwrap();
+        write_path.put(b"key1".to_vec(), b"value1".to_vec()).unwrap();
+
+        write_path
+            .close_with(&CloseOptions { cancel_background_work: true })
+            .unwrap();
+
+        // 未フラッシュのMemTableは書き出されない
+        let sst_files = fs::read_dir(temp_dir.path())
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "sst"))
+            .count();
+        assert_eq!(sst_files, 0);
+    }
+
+    #[test]
+    fn test_compressed_sstables_can_be_read_back() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let options = Options::builder()
+            .write_buffer_size(64 * 1024)
+            .block_size(1024)
+            .compression(CompressionType::Zstd)
+            .build()
+            .unwrap();
+        let write_path = WritePath::open(temp_dir.path(), options).unwrap();
+
+        let expected: Vec<(Vec<u8>, Vec<u8>)> = (0..2000u64)
+            .map(|i| (format!("{:016}", i).into_bytes(), vec![b'x'; 100]))
+            .collect();
+        for (key, value) in &expected {
+            write_path.put(key.clone(), value.clone()).unwrap();
+        }
+        write_path.close().unwrap();
+
+        assert_eq!(read_entries(temp_dir.path()), expected);
+
+        // 116 bytes x 2000 = 約232KBが圧縮されている
+        let total_size: u64 = fs::read_dir(temp_dir.path())
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "sst"))
+            .map(|e| e.metadata().unwrap().len())
+            .sum();
+        assert!(total_size < 100 * 1024, "total_size = {}", total_size);
+    }
 }
@@ -6,6 +6,8 @@ use std::sync::mpsc::{sync_channel, SyncSender, Receiver};
 use std::thread::{self, JoinHandle};
 use crossbeam_skiplist::SkipMap;
 
+use crate::error::{Error, Result};
+
 /// Mutable なバッファ（SkipMap版）
 struct MemTable {
     entries: SkipMap<Vec<u8>, Vec<u8>>,
@@ -54,16 +56,23 @@ pub struct WritePath {
     /// バックグラウンドスレッドのハンドル
     flush_thread: Option<JoinHandle<()>>,
     /// 出力ディレクトリ
+    #[allow(dead_code)]
     data_dir: PathBuf,
     /// SSTableファイルのカウンター
+    #[allow(dead_code)]
     sstable_counter: Arc<Mutex<usize>>,
     /// immutable MemTableの最大数（RocksDBのmax_write_buffer_number相当）
+    #[allow(dead_code)]
     max_write_buffer_number: usize,
+    /// 最初のフラッシュ失敗（Someの間はput/flushで`Error::BackgroundError`を返す）
+    ///
+    /// 失敗したMemTableは再試行しない
+    bg_error: Arc<Mutex<Option<Arc<Error>>>>,
 }
 
 impl WritePath {
     /// 新しいWritePathを作成（デフォルトのmax_write_buffer_number = 2）
-    pub fn new<P: AsRef<Path>>(data_dir: P, size_threshold: usize) -> std::io::Result<Self> {
+    pub fn new<P: AsRef<Path>>(data_dir: P, size_threshold: usize) -> Result<Self> {
         Self::with_max_write_buffers(data_dir, size_threshold, 2)
     }
 
@@ -72,7 +81,7 @@ impl WritePath {
         data_dir: P,
         size_threshold: usize,
         max_write_buffer_number: usize,
-    ) -> std::io::Result<Self> {
+    ) -> Result<Self> {
         let data_dir = data_dir.as_ref().to_path_buf();
 
         // データディレクトリを作成
@@ -83,9 +92,11 @@ impl WritePath {
         let buffer_capacity = max_write_buffer_number.saturating_sub(1).max(1);
         let (tx, rx) = sync_channel(buffer_capacity);
         let sstable_counter = Arc::new(Mutex::new(0));
+        let bg_error = Arc::new(Mutex::new(None));
 
         // バックグラウンドフラッシュスレッドを起動
-        let flush_thread = Self::spawn_flush_thread(rx, data_dir.clone(), sstable_counter.clone());
+        let flush_thread =
+            Self::spawn_flush_thread(rx, data_dir.clone(), sstable_counter.clone(), bg_error.clone());
 
         Ok(Self {
             memtable: Arc::new(Mutex::new(MemTable::new())),
@@ -95,14 +106,25 @@ impl WritePath {
             data_dir,
             sstable_counter,
             max_write_buffer_number,
+            bg_error,
         })
     }
 
+    /// フラッシュが失敗していればそのエラーを返す
+    fn check_bg_error(&self) -> Result<()> {
+        match &*self.bg_error.lock().unwrap() {
+            Some(e) => Err(Error::BackgroundError(e.clone())),
+            None => Ok(()),
+        }
+    }
+
     /// キーと値を書き込む
     ///
     /// immutable MemTableの数が上限に達している場合、
-    /// フラッシュが完了するまで書き込みがブロックされる（write stall）
-    pub fn put(&self, key: Vec<u8>, value: Vec<u8>) -> std::io::Result<()> {
+    /// フラッシュが完了するまで書き込みがブロックされる（write stall）。
+    /// バックグラウンドフラッシュが失敗していればそのエラーを返す
+    pub fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.check_bg_error()?;
         let mut memtable = self.memtable.lock().unwrap();
 
         memtable.put(key, value);
@@ -117,15 +139,15 @@ impl WritePath {
     }
 
     /// 現在のmemtableをimmutable化して新しいmemtableを作成
-    fn freeze_memtable(&self, memtable: &mut std::sync::MutexGuard<MemTable>) -> std::io::Result<()> {
+    fn freeze_memtable(&self, memtable: &mut std::sync::MutexGuard<MemTable>) -> Result<()> {
         // 古いmemtableを取り出し、新しいmemtableと交換
         let old_memtable = std::mem::replace(&mut **memtable, MemTable::new());
 
         // バックグラウンドスレッドに送信
         if !old_memtable.is_empty() {
             if let Some(sender) = &self.flush_sender {
-                sender.send(old_memtable)
-                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+                // 受信側がいない = フラッシュスレッドが終了している
+                sender.send(old_memtable).map_err(|_| Error::ShutdownInProgress)?;
             }
         }
 
@@ -133,7 +155,8 @@ impl WritePath {
     }
 
     /// 明示的にフラッシュ（すべてのデータをディスクに書き出す）
-    pub fn flush(&self) -> std::io::Result<()> {
+    pub fn flush(&self) -> Result<()> {
+        self.check_bg_error()?;
         let mut memtable = self.memtable.lock().unwrap();
         if !memtable.is_empty() {
             self.freeze_memtable(&mut memtable)?;
@@ -146,11 +169,12 @@ impl WritePath {
         rx: Receiver<MemTable>,
         data_dir: PathBuf,
         counter: Arc<Mutex<usize>>,
+        bg_error: Arc<Mutex<Option<Arc<Error>>>>,
     ) -> JoinHandle<()> {
         thread::spawn(move || {
             while let Ok(memtable) = rx.recv() {
                 if let Err(e) = Self::write_sstable(&data_dir, &memtable, &counter) {
-                    eprintln!("Failed to write SSTable: {}", e);
+                    bg_error.lock().unwrap().get_or_insert_with(|| Arc::new(e));
                 }
             }
         })
@@ -161,7 +185,7 @@ impl WritePath {
         data_dir: &Path,
         memtable: &MemTable,
         counter: &Arc<Mutex<usize>>,
-    ) -> std::io::Result<()> {
+    ) -> Result<()> {
         let file_num = {
             let mut c = counter.lock().unwrap();
             let num = *c;
@@ -234,7 +258,7 @@ mod tests {
         let files: Vec<_> = fs::read_dir(temp_dir.path())
             .unwrap()
             .filter_map(|e| e.ok())
-            .filter(|e| e.path().extension().map_or(false, |ext| ext == "sst"))
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "sst"))
             .collect();
 
         assert!(!files.is_empty(), "SSTable file should be created");
@@ -258,10 +282,40 @@ mod tests {
         let files: Vec<_> = fs::read_dir(temp_dir.path())
             .unwrap()
             .filter_map(|e| e.ok())
-            .filter(|e| e.path().extension().map_or(false, |ext| ext == "sst"))
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "sst"))
             .collect();
 
         assert!(!files.is_empty(), "SSTable file should be created");
         // Note: ソート順の検証は実際のファイル内容を読む必要がある
     }
+
+    #[test]
+    fn test_flush_failure_is_returned() {
+        use std::time::{Duration, Instant};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let data_dir = temp_dir.path().join("db");
+        let write_path = WritePath::new(&data_dir, 1024).unwrap();
+
+        // ディレクトリを消してSSTableの作成を失敗させる
+        fs::remove_dir_all(&data_dir).unwrap();
+        write_path.put(b"key1".to_vec(), b"value1".to_vec()).unwrap();
+        write_path.flush().unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let err = loop {
+            match write_path.put(b"key2".to_vec(), b"value2".to_vec()) {
+                Err(e) => break e,
+                Ok(()) => {
+                    assert!(Instant::now() < deadline, "flush failure was not reported");
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+        };
+        match &err {
+            Error::BackgroundError(cause) => assert!(matches!(cause.as_ref(), Error::Io(_)), "{:?}", cause),
+            other => panic!("unexpected error: {:?}", other),
+        }
+        assert!(matches!(write_path.flush(), Err(Error::BackgroundError(_))));
+    }
 }